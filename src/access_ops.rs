@@ -0,0 +1,133 @@
+use crate::data_types::{AccessRule, Auth, AuthUser, CustomError, Permission, TableConfig, TableRegistry};
+use tracing::warn;
+use crate::db_ops::get_backend_table;
+use crate::registry_ops::get_table_config;
+
+// access control per table , from the 'access' rules of the registry and the roles of the user (app_user_roles)
+//
+//   access:
+//     - role: sales_emea
+//       permissions: [view, export]
+//       row_filter: "region = 'emea'"
+//       masked_columns: [md5]
+//
+// - a table without rules is open to every user , a table with rules only to the roles listed
+// - several roles of a user : the rows of any of them (row filters OR-ed) , a column stays masked only
+//   when every role masks it
+// - every request works on a view of the table for its user : the row filter ends up in the WHERE clause of the
+//   page , the counts and the exports , masked columns are read as MASKED_VALUE
+// - without authentication (AUTH.MODE=none) the rules are not applied
+
+pub const MASKED_VALUE: &str = "****";
+
+const ALL_PERMISSIONS: [Permission; 3] = [Permission::View, Permission::Export, Permission::Admin];
+
+fn grants(rule: &AccessRule, permission: Permission) -> bool {
+    rule.permissions.iter().any(|p| *p >= permission)
+}
+
+// the rules of the table for the user , None when the rules do not apply (no rules , no authentication)
+fn get_user_rules<'a>(table: &'a TableConfig, user: Option<&AuthUser>) -> Option<Vec<&'a AccessRule>> {
+    match user {
+        Some(user) if !table.access.is_empty() => Some(table.access.iter().filter(|r| user.roles.contains(&r.role)).collect()),
+        _ => None,
+    }
+}
+
+// the table as the user may use it for 'permission' , None when they may not
+pub fn get_table_view(table: &TableConfig, user: Option<&AuthUser>, permission: Permission) -> Option<TableConfig> {
+    let mut view = table.clone();
+    let rules = match get_user_rules(table, user) {
+        Some(r) => r,
+        None => {
+            view.permissions = ALL_PERMISSIONS.to_vec();
+            return Some(view)
+        }
+    };
+
+    view.permissions = ALL_PERMISSIONS.iter().filter(|p| rules.iter().any(|r| grants(r, **p))).copied().collect();
+
+    let rules: Vec<&AccessRule> = rules.into_iter().filter(|r| grants(r, permission)).collect();
+    if rules.is_empty() {
+        return None
+    }
+
+    // a rule without a row filter gives every row
+    if rules.iter().all(|r| r.row_filter.is_some()) {
+        let filters: Vec<String> = rules.iter().filter_map(|r| r.row_filter.as_ref()).map(|f| format!("( {} )", f)).collect();
+        view.row_filter = Some(filters.join(" OR "));
+    }
+
+    for column in view.column_info.iter_mut() {
+        column.masked = rules.iter().all(|r| r.masked_columns.contains(&column.name));
+    }
+
+    Some(view)
+}
+
+// unknown tables and tables the user may not use for 'permission' are both refused
+pub fn get_table_view_for_user(registry: &TableRegistry, table_short_name: &str, user: Option<&AuthUser>, permission: Permission) -> Result<TableConfig, CustomError> {
+    let table = get_table_config(registry, table_short_name)?;
+    match get_table_view(table, user, permission) {
+        Some(view) => Ok(view),
+        None => {
+            warn!("({}) has no {} permission on table ({})", user.map_or("-", |u| u.username.as_str()), permission.name(), table_short_name);
+            Err(CustomError::Forbidden(format!("no {} permission on table ({})", permission.name(), table_short_name)))
+        }
+    }
+}
+
+// the tables of the UI and of GET /api/v1/tables
+pub fn get_visible_tables(registry: &TableRegistry, user: Option<&AuthUser>) -> Vec<TableConfig> {
+    registry.tables.iter().filter_map(|t| get_table_view(t, user, Permission::View)).collect()
+}
+
+// exports are managed by the users with the role EXPORT.ADMIN_ROLE , and by the roles granted 'admin' in the
+// rules of their table >> a table without rules (or no longer in the registry) has no admin of its own
+pub fn can_admin_exports(registry: &TableRegistry, admin_role: &str, user: Option<&AuthUser>, table_short_name: &str) -> bool {
+    let user = match user {
+        Some(u) => u,
+        None => return true,    // without authentication every route is open
+    };
+    if user.roles.iter().any(|r| r == admin_role) {
+        return true
+    }
+    match registry.tables.iter().find(|t| t.short_name == table_short_name) {
+        Some(t) if !t.access.is_empty() => get_table_view(t, Some(user), Permission::Admin).is_some(),
+        _ => false,
+    }
+}
+
+pub fn check_access_rules(registry: &TableRegistry, auth: &Auth) {
+    if auth.backend.is_none() && registry.tables.iter().any(|t| !t.access.is_empty()) {
+        warn!("AUTH.MODE is 'none' , the access rules of the table registry are not applied");
+    }
+}
+
+/* ************************************************************************************* */
+
+// masked columns must be columns of the table , a row filter must be valid SQL on the table
+pub async fn validate_access_rules(client: &deadpool_postgres::Client, table: &TableConfig) -> Result<(), CustomError> {
+    for rule in &table.access {
+        if rule.role.trim().is_empty() || rule.permissions.is_empty() {
+            return Err(CustomError::ConfigError(format!("table ({}) : every access rule needs a role and permissions", table.short_name)))
+        }
+        for column in &rule.masked_columns {
+            if !table.columns.contains(column) {
+                return Err(CustomError::ConfigError(format!("table ({}) : masked column ({}) of role ({}) is not a column of the table", table.short_name, column, rule.role)))
+            }
+        }
+        if let Some(row_filter) = &rule.row_filter {
+            if row_filter.trim().is_empty() || row_filter.contains(';') {
+                return Err(CustomError::ConfigError(format!("table ({}) : invalid row filter of role ({})", table.short_name, rule.role)))
+            }
+            // the predicate is planned (not run) against the table
+            let sql_query = format!("EXPLAIN SELECT 1 FROM {} WHERE ( {} )", get_backend_table(table).await, row_filter);
+            if let Err(e) = client.query(sql_query.as_str(), &[]).await {
+                let reason = e.as_db_error().map_or(e.to_string(), |d| d.message().to_string());
+                return Err(CustomError::ConfigError(format!("table ({}) : invalid row filter of role ({}) : {}", table.short_name, rule.role, reason)))
+            }
+        }
+    }
+    Ok(())
+}
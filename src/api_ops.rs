@@ -0,0 +1,179 @@
+use deadpool_postgres::Pool;
+use serde_json::Value;
+use crate::data_types::{ApiFilter, ApiRowsRequest, ApiRowsResponse, ApiSort, ApiTableSchema, ApiTableSummary, ApiTablesResponse, CompareOp, CustomError, PageRequest, SearchExpr, SearchFilters, SearchOp, SearchTerm, SortColumn, TableConfig};
+use crate::fulltext_ops::get_fulltext_config;
+use crate::fuzzy_ops::check_fuzzy_search;
+use crate::page_ops::get_table_page;
+use crate::search_ops::parse_search_expression;
+
+// JSON API for scripts , next to the DataTables form protocol of /query
+//   GET  /api/v1/tables                >> the tables of the registry
+//   GET  /api/v1/tables/{name}/schema  >> columns and their types
+//   POST /api/v1/tables/{name}/rows    >> filters , sort , projection and pagination , typed rows and counts
+
+// also the largest page of /query (see datatables_ops)
+pub const MAX_API_LIMIT: u32 = 10000;
+
+// the tables the caller may see (see access_ops)
+pub fn get_api_tables(tables: &[TableConfig]) -> ApiTablesResponse {
+    ApiTablesResponse {
+        tables: tables.iter().map(|t| ApiTableSummary {
+            name: t.short_name.to_string(),
+            title: t.title.to_string(),
+            columns: t.columns.clone(),
+        }).collect(),
+    }
+}
+
+pub fn get_api_table_schema(table: &TableConfig) -> ApiTableSchema {
+    let mut pattern_matches: Vec<String> = ["like", "exact", "fulltext", "regex"].iter().map(|p| p.to_string()).collect();
+    if table.has_pg_trgm {
+        pattern_matches.push("fuzzy".to_string());
+    }
+
+    ApiTableSchema {
+        name: table.short_name.to_string(),
+        title: table.title.to_string(),
+        columns: table.column_info.clone(),
+        pattern_matches,
+        fulltext_columns: get_fulltext_config(table).columns,
+        key_column: table.keyset.as_ref()
+            .filter(|k| !table.column_info.iter().any(|c| c.name == k.column && c.masked))
+            .map(|k| k.column.to_string()),
+        permissions: table.permissions.clone(),
+    }
+}
+
+/* ************************************************************************************* */
+
+// typed JSON value >> text , the search compares it with the type of the column
+fn get_filter_value(filter: &ApiFilter, value: &Value) -> Result<Option<String>, CustomError> {
+    match value {
+        Value::String(s) => Ok(Some(s.to_string())),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        Value::Bool(b) => Ok(Some(b.to_string())),
+        Value::Null => Ok(None),
+        _ => Err(CustomError::ValidationError(format!("invalid value ({}) for the filter on column ({})", value, filter.column)))
+    }
+}
+
+fn get_filter_expression(filter: &ApiFilter) -> Result<SearchExpr, CustomError> {
+    let compare_op = match filter.op.as_str() {
+        "eq" => Some(CompareOp::Eq),
+        "ne" => Some(CompareOp::Ne),
+        "gt" => Some(CompareOp::Gt),
+        "ge" => Some(CompareOp::Ge),
+        "lt" => Some(CompareOp::Lt),
+        "le" => Some(CompareOp::Le),
+        _ => None,
+    };
+
+    let op = match (compare_op, filter.op.as_str(), &filter.value) {
+        (Some(op), _, value) => match get_filter_value(filter, value)? {
+            Some(v) => SearchOp::Compare(op, v),
+            None => return Err(CustomError::ValidationError(format!("filter ({}) on column ({}) needs a value", filter.op, filter.column)))
+        },
+        (None, "between", Value::Array(bounds)) if bounds.len() == 2 => {
+            match (get_filter_value(filter, &bounds[0])?, get_filter_value(filter, &bounds[1])?) {
+                (None, None) => return Err(CustomError::ValidationError(format!("filter (between) on column ({}) needs at least one bound", filter.column))),
+                (from, to) => SearchOp::Range(from, to),
+            }
+        },
+        (None, "between", _) => return Err(CustomError::ValidationError(format!("filter (between) on column ({}) needs a value [from, to]", filter.column))),
+        (None, "match", value) => match get_filter_value(filter, value)? {
+            Some(v) => SearchOp::Match(v),
+            None => return Err(CustomError::ValidationError(format!("filter (match) on column ({}) needs a value", filter.column)))
+        },
+        _ => return Err(CustomError::ValidationError(format!("invalid filter op ({}) , valid values : 'eq' , 'ne' , 'gt' , 'ge' , 'lt' , 'le' , 'between' , 'match'", filter.op)))
+    };
+
+    Ok(SearchExpr::Term(SearchTerm { column: Some(filter.column.to_string()), op }))
+}
+
+fn get_api_sort_columns(table: &TableConfig, sort: &[ApiSort]) -> Result<Vec<SortColumn>, CustomError> {
+    sort.iter().map(|s| {
+        match table.column_info.iter().find(|c| c.name == s.column) {
+            Some(c) if c.masked => return Err(CustomError::ValidationError(format!("column ({}) is masked and cannot be sorted on", s.column))),
+            Some(_) => (),
+            None => return Err(CustomError::ValidationError(format!("invalid sort column ({})", s.column))),
+        }
+        let descending = match s.dir.to_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(CustomError::ValidationError(format!("invalid sort direction ({})", s.dir)))
+        };
+        Ok(SortColumn { name: s.column.to_string(), descending })
+    }).collect()
+}
+
+// the search and the filters of the request , as recorded in the audit log
+// example : 'xyz ; random_num gt 500 ; my_date between ["2022-01-01",null]'
+pub fn get_api_search_description(request: &ApiRowsRequest) -> String {
+    let mut searches = vec![];
+    if !request.search.trim().is_empty() {
+        searches.push(request.search.trim().to_string());
+    }
+    for filter in &request.filters {
+        searches.push(format!("{} {} {}", filter.column, filter.op, filter.value));
+    }
+    searches.join(" ; ")
+}
+
+// the search and the filters are AND-ed together , a filter on an unknown column is a validation error
+pub async fn get_api_rows(table: &TableConfig, pool: &Pool, request: &ApiRowsRequest) -> Result<ApiRowsResponse, CustomError> {
+    if request.limit == 0 || request.limit > MAX_API_LIMIT {
+        return Err(CustomError::ValidationError(format!("limit must be between 1 and {}", MAX_API_LIMIT)))
+    }
+    for column in &request.columns {
+        if !table.columns.contains(column) {
+            return Err(CustomError::ValidationError(format!("unknown column ({})", column)))
+        }
+    }
+
+    check_fuzzy_search(table, request.pattern_match.as_str())?;
+
+    let mut conditions = vec![];
+    if let Some(search) = parse_search_expression(request.search.as_str(), &table.columns, request.pattern_match.as_str())? {
+        conditions.push(search);
+    }
+    for filter in &request.filters {
+        conditions.push(get_filter_expression(filter)?);
+    }
+    let search = match conditions.len() {
+        0 => None,
+        1 => conditions.pop(),
+        _ => Some(SearchExpr::And(conditions)),
+    };
+
+    let search_filters = SearchFilters {
+        search,
+        pattern_match: request.pattern_match.to_string(),
+        columns: table.column_info.clone(),
+        fulltext: get_fulltext_config(table),
+        searchable_columns: table.columns.clone(),
+        column_searches: vec![],
+    };
+
+    let sort_columns = get_api_sort_columns(table, &request.sort)?;
+
+    let page = PageRequest {
+        start: request.offset,
+        length: request.limit,
+        cursor: request.cursor.to_string(),
+    };
+
+    let table_page = get_table_page(table, pool, &search_filters, &sort_columns, &request.columns, &page).await?;
+
+    Ok(ApiRowsResponse {
+        table: table.short_name.to_string(),
+        columns: if request.columns.is_empty() { table.columns.clone() } else { request.columns.clone() },
+        rows: table_page.rows,
+        total: table_page.records_total,
+        filtered: table_page.records_filtered,
+        offset: request.offset,
+        limit: request.limit,
+        next_cursor: table_page.next_cursor,
+        prev_cursor: table_page.prev_cursor,
+        scores: table_page.scores,
+    })
+}
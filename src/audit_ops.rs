@@ -0,0 +1,364 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+use actix_web::{web, HttpRequest};
+use async_trait::async_trait;
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use crate::data_types::{Audit, AuditConfig, AuditEntry, AuditEvent, AuditPageRequest, AuthUser, CustomError, DataTablesRequest, DbPools, SortColumn, TablePage};
+use crate::db_ops::{fetch_as_json, get_count_of_records, get_optional_env_var, make_db_pool};
+use crate::download_ops::get_client_identity;
+use crate::query_ops::{get_order_by_clause, SqlQuery};
+use crate::request_ops::{get_client_ip, get_request_id};
+use crate::string_ops::{quote_identifier, sanitize_search_string};
+
+// who searched for what , who exported which data : one entry per /query page , JSON API page and export
+//   AUDIT.MODE=file     : append-only JSON Lines file (AUDIT.FILE)
+//   AUDIT.MODE=postgres : the audit table (AUDIT.POOL , AUDIT.SCHEMA)
+//   AUDIT.MODE=none     : no audit log
+//
+//   CREATE TABLE app_audit_log (id bigserial PRIMARY KEY , created_at timestamptz NOT NULL , request_id text NOT NULL ,
+//                               username text NOT NULL , client_ip text NOT NULL , action text NOT NULL , table_name text NOT NULL ,
+//                               search text NOT NULL , pattern_match text NOT NULL , rows bigint , duration_ms bigint NOT NULL ,
+//                               export_file text , status text NOT NULL);
+//   CREATE INDEX ON app_audit_log (created_at);
+//
+// entries are written in the background , a failing audit log does not fail the request (the error is logged)
+// the log is browsed with the DataTables UI at /audit (users with the role AUDIT.ROLE)
+
+const AUDIT_TABLE: &str = "app_audit_log";
+
+// columns of the audit log , in the order of the UI
+pub const AUDIT_COLUMNS: [&str; 12] = ["created_at", "username", "client_ip", "action", "table_name", "search", "pattern_match", "rows", "duration_ms", "export_file", "status", "request_id"];
+
+// where audit entries are written and read back , one implementation per AUDIT.MODE
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn write(&self, entry: &AuditEntry) -> Result<(), CustomError>;
+    async fn read_page(&self, request: &AuditPageRequest) -> Result<TablePage, CustomError>;
+}
+
+pub fn load_audit_config() -> Result<AuditConfig, CustomError> {
+    let mode = get_optional_env_var::<String>("AUDIT.MODE")?.unwrap_or_else(|| "file".to_string());
+    if !matches!(mode.as_str(), "file" | "postgres" | "none") {
+        return Err(CustomError::ConfigError(format!("invalid AUDIT.MODE ({}) , valid values : 'file' , 'postgres' , 'none'", mode)))
+    }
+
+    let config = AuditConfig {
+        mode,
+        file: get_optional_env_var::<String>("AUDIT.FILE")?.unwrap_or_else(|| "audit_log.jsonl".to_string()),
+        pool: get_optional_env_var::<String>("AUDIT.POOL")?.unwrap_or_else(|| "default".to_string()),
+        schema: get_optional_env_var::<String>("AUDIT.SCHEMA")?.unwrap_or_else(|| "public".to_string()),
+        role: get_optional_env_var::<String>("AUDIT.ROLE")?.unwrap_or_else(|| "auditor".to_string()),
+    };
+
+    match config.mode.as_str() {
+        "file" => info!("audit log : file ({}) , browsed by role ({})", config.file, config.role),
+        "postgres" => info!("audit log : table ({}.{}) in pool ({}) , browsed by role ({})", config.schema, AUDIT_TABLE, config.pool, config.role),
+        _ => warn!("AUDIT.MODE is 'none' , searches and exports are not recorded"),
+    }
+
+    Ok(config)
+}
+
+// the audit file (or table) has to be writable at startup , the pool is created when no table of the registry uses it
+pub async fn make_audit(config: AuditConfig, pools: &mut DbPools) -> Result<Audit, CustomError> {
+    let sink: Option<Arc<dyn AuditSink>> = match config.mode.as_str() {
+        "file" => {
+            let sink = FileAuditSink { file_path: config.file.to_string(), lock: Mutex::new(()) };
+            sink.check_file().await?;
+            Some(Arc::new(sink))
+        },
+        "postgres" => {
+            if !pools.pools.contains_key(&config.pool) {
+                let pool = make_db_pool(config.pool.as_str()).await?;
+                pools.pools.insert(config.pool.to_string(), pool);
+            }
+            let pool = pools.pools[&config.pool].clone();
+            let sink = PgAuditSink { pool, schema: config.schema.to_string() };
+            sink.check_table().await?;
+            Some(Arc::new(sink))
+        },
+        _ => None,
+    };
+
+    Ok(Audit { config, sink })
+}
+
+/* ************************************************************************************* */
+
+// the entry of a request , 'rows' , 'status' and the duration are set by finish_audit_event
+// the audit log is read from the app data , like the authentication of with_auth
+pub fn start_audit_event(req: &HttpRequest, action: &str) -> AuditEvent {
+    AuditEvent {
+        sink: req.app_data::<web::Data<Audit>>().and_then(|a| a.sink.clone()),
+        entry: AuditEntry {
+            created_at: Utc::now(),
+            request_id: get_request_id(),
+            username: get_client_identity(req),
+            client_ip: get_client_ip(req),
+            action: action.to_string(),
+            table_name: String::new(),
+            search: String::new(),
+            pattern_match: String::new(),
+            rows: None,
+            duration_ms: 0,
+            export_file: None,
+            status: String::new(),
+        },
+        started_at: Instant::now(),
+    }
+}
+
+// status : 'ok' , 'cancelled' or the code of the error
+pub fn finish_audit_event(event: AuditEvent, rows: Option<i64>, status: &str) {
+    let sink = match event.sink {
+        Some(s) => s,
+        None => return
+    };
+    let mut entry = event.entry;
+    entry.rows = rows;
+    entry.status = status.to_string();
+    entry.duration_ms = event.started_at.elapsed().as_millis() as i64;
+
+    tokio::spawn(async move {
+        if let Err(e) = sink.write(&entry).await {
+            error!("could not write the audit entry of request ({}) : {}", entry.request_id, e);
+        }
+    });
+}
+
+// only users with the role AUDIT.ROLE (everybody without authentication)
+pub fn check_audit_access(audit: &Audit, user: Option<&AuthUser>) -> Result<(), CustomError> {
+    if audit.sink.is_none() {
+        return Err(CustomError::NotFound("the audit log is disabled (AUDIT.MODE)".to_string()))
+    }
+    match user {
+        Some(u) if !u.roles.contains(&audit.config.role) => {
+            warn!("({}) may not browse the audit log", u.username);
+            Err(CustomError::Forbidden(format!("the audit log needs the role ({})", audit.config.role)))
+        },
+        _ => Ok(()),
+    }
+}
+
+// DataTables request of /audit/query >> global search and the first sorted column
+pub fn get_audit_page_request(request: &DataTablesRequest) -> Result<AuditPageRequest, CustomError> {
+    let sort = match request.order.first() {
+        Some(order) => {
+            let name = match AUDIT_COLUMNS.get(order.column) {
+                Some(c) => c.to_string(),
+                None => return Err(CustomError::ValidationError(format!("invalid sort column index ({})", order.column)))
+            };
+            let descending = match order.dir.to_lowercase().as_str() {
+                "asc" => false,
+                "desc" => true,
+                _ => return Err(CustomError::ValidationError(format!("invalid sort direction ({})", order.dir)))
+            };
+            Some(SortColumn { name, descending })
+        },
+        None => None,
+    };
+
+    Ok(AuditPageRequest {
+        start: request.start,
+        length: request.length,
+        search: request.search_value.to_string(),
+        sort,
+    })
+}
+
+pub async fn get_audit_page(audit: &Audit, request: &AuditPageRequest) -> Result<TablePage, CustomError> {
+    match &audit.sink {
+        Some(sink) => sink.read_page(request).await,
+        None => Err(CustomError::NotFound("the audit log is disabled (AUDIT.MODE)".to_string()))
+    }
+}
+
+/* ************************************************************************************* */
+
+struct FileAuditSink {
+    file_path: String,
+    lock: Mutex<()>,     // one line at a time
+}
+
+impl FileAuditSink {
+    async fn check_file(&self) -> Result<(), CustomError> {
+        match tokio::fs::OpenOptions::new().create(true).append(true).open(self.file_path.as_str()).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("could not open the audit file ({}) : {}", self.file_path, e);
+                Err(CustomError::ConfigError(format!("audit file ({}) is not writable", self.file_path)))
+            }
+        }
+    }
+}
+
+fn get_entry_text(entry: &Value) -> String {
+    AUDIT_COLUMNS.iter()
+        .filter_map(|c| match &entry[*c] {
+            Value::String(s) => Some(s.to_lowercase()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// numbers as numbers , everything else (timestamps are RFC 3339) as text
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64().partial_cmp(&y.as_f64()).unwrap_or(Ordering::Equal),
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileAuditSink {
+    async fn write(&self, entry: &AuditEntry) -> Result<(), CustomError> {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(l) => l,
+            Err(e) => return Err(CustomError::ExportError(format!("could not serialize the audit entry : {}", e)))
+        };
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = match tokio::fs::OpenOptions::new().create(true).append(true).open(self.file_path.as_str()).await {
+            Ok(f) => f,
+            Err(e) => return Err(CustomError::ExportError(format!("could not open the audit file ({}) : {}", self.file_path, e)))
+        };
+        match file.write_all(&line).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(CustomError::ExportError(format!("could not write the audit file ({}) : {}", self.file_path, e)))
+        }
+    }
+
+    // the whole file is read for every page , the 'postgres' mode is meant for large logs
+    async fn read_page(&self, request: &AuditPageRequest) -> Result<TablePage, CustomError> {
+        let contents = match tokio::fs::read_to_string(self.file_path.as_str()).await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("could not read the audit file ({}) : {}", self.file_path, e);
+                return Err(CustomError::ExportError("could not read the audit log".to_string()))
+            }
+        };
+
+        // newest first
+        let mut entries: Vec<Value> = contents.lines().rev().filter_map(|l| serde_json::from_str(l).ok()).collect();
+        let records_total = entries.len() as i64;
+
+        let search = request.search.trim().to_lowercase();
+        if !search.is_empty() {
+            entries.retain(|e| get_entry_text(e).contains(search.as_str()));
+        }
+        if let Some(sort) = &request.sort {
+            entries.sort_by(|a, b| {
+                let ordering = compare_values(&a[sort.name.as_str()], &b[sort.name.as_str()]);
+                if sort.descending { ordering.reverse() } else { ordering }
+            });
+        }
+
+        Ok(TablePage {
+            records_total,
+            records_filtered: entries.len() as i64,
+            rows: entries.into_iter().skip(request.start as usize).take(request.length as usize).collect(),
+            next_cursor: None,
+            prev_cursor: None,
+            scores: None,
+        })
+    }
+}
+
+/* ************************************************************************************* */
+
+struct PgAuditSink {
+    pool: Pool,
+    schema: String,
+}
+
+impl PgAuditSink {
+    fn get_table(&self) -> String {
+        format!("{}.{}", quote_identifier(self.schema.as_str()), quote_identifier(AUDIT_TABLE))
+    }
+
+    async fn check_table(&self) -> Result<(), CustomError> {
+        let client = match self.pool.get().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("could not get a db connection : {}", e);
+                return Err(CustomError::DatabaseError("database is unavailable, please try again".to_string()))
+            }
+        };
+        let sql_query = format!("SELECT {} FROM {} LIMIT 0", AUDIT_COLUMNS.join(", "), self.get_table());
+        if let Err(e) = client.query(sql_query.as_str(), &[]).await {
+            error!("audit table ({}) is not readable : {}", self.get_table(), e.as_db_error().map_or(e.to_string(), |d| d.message().to_string()));
+            return Err(CustomError::ConfigError(format!("audit table ({}) does not exist in schema ({})", AUDIT_TABLE, self.schema)))
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuditSink for PgAuditSink {
+    async fn write(&self, entry: &AuditEntry) -> Result<(), CustomError> {
+        let client = match self.pool.get().await {
+            Ok(c) => c,
+            Err(e) => return Err(CustomError::DatabaseError(format!("could not get a db connection : {}", e)))
+        };
+        let sql_query = format!("INSERT INTO {} (created_at, request_id, username, client_ip, action, table_name, search, pattern_match, rows, duration_ms, export_file, status) \
+                                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)", self.get_table());
+        match client.execute(sql_query.as_str(), &[&entry.created_at, &entry.request_id, &entry.username, &entry.client_ip, &entry.action, &entry.table_name,
+                                                    &entry.search, &entry.pattern_match, &entry.rows, &entry.duration_ms, &entry.export_file, &entry.status]).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(CustomError::DatabaseError(format!("could not insert into the audit table : {}", e)))
+        }
+    }
+
+    async fn read_page(&self, request: &AuditPageRequest) -> Result<TablePage, CustomError> {
+        let mut where_clause = SqlQuery::new();
+        if !request.search.trim().is_empty() {
+            let placeholder = where_clause.bind(sanitize_search_string(request.search.trim(), "like")?);
+            where_clause.sql = format!("WHERE concat_ws(' ', {}) ILIKE {}", AUDIT_COLUMNS.join(", "), placeholder);
+        }
+
+        let order_by_clause = match &request.sort {
+            Some(sort) => get_order_by_clause(std::slice::from_ref(sort)),
+            None => "ORDER BY created_at DESC".to_string(),
+        };
+
+        let select_query = SqlQuery {
+            sql: format!("SELECT {} FROM {} {} {} LIMIT {} OFFSET {}", AUDIT_COLUMNS.join(", "), self.get_table(), where_clause.sql, order_by_clause, request.length, request.start),
+            params: where_clause.params.clone(),
+        };
+        let filtered_count_query = SqlQuery {
+            sql: format!("SELECT count(*) FROM {} {}", self.get_table(), where_clause.sql),
+            params: where_clause.params.clone(),
+        };
+        let total_count_query = SqlQuery {
+            sql: format!("SELECT count(*) FROM {}", self.get_table()),
+            params: vec![],
+        };
+
+        let (rows, records_filtered, records_total) = futures::try_join!(
+            fetch_as_json(&self.pool, &select_query),
+            get_count_of_records(&filtered_count_query, &self.pool),
+            get_count_of_records(&total_count_query, &self.pool)
+        )?;
+
+        Ok(TablePage {
+            rows,
+            records_total,
+            records_filtered,
+            next_cursor: None,
+            prev_cursor: None,
+            scores: None,
+        })
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn, Span};
+use uuid::Uuid;
+use crate::data_types::{ApiTokenRequest, ApiTokenResponse, Auth, AuthConfig, AuthSession, AuthUser, CustomError, DbPools, ProxyConfig};
+use crate::db_ops::{get_optional_env_var, make_db_pool};
+use crate::string_ops::{percent_encode, quote_identifier};
+
+// AUTH.MODE=local : every route but the login page needs a user
+//   - the UI : session cookie set by POST /login (users and their argon2 password hashes in postgres)
+//   - scripts : 'Authorization: Bearer <token>' , tokens are created with POST /auth/tokens
+// AUTH.MODE=none : no authentication (local development only)
+//
+//   CREATE TABLE app_users (username text PRIMARY KEY , password_hash text NOT NULL , is_active boolean NOT NULL DEFAULT true);
+//   CREATE TABLE app_api_tokens (token_hash text PRIMARY KEY , username text NOT NULL REFERENCES app_users ,
+//                                name text NOT NULL , expires_at timestamptz , created_at timestamptz NOT NULL DEFAULT now());
+//   CREATE TABLE app_user_roles (username text NOT NULL REFERENCES app_users , role text NOT NULL , PRIMARY KEY (username, role));
+//
+// the roles of a user are matched with the 'access' rules of the tables (see access_ops.rs) ,
+// a session keeps the roles read at login , an API token reads them on every request
+//
+// password hashes are made with : ./rust-datatable hash-password (reads the password from stdin)
+
+pub const SESSION_COOKIE: &str = "dt_session";
+const USERS_TABLE: &str = "app_users";
+const TOKENS_TABLE: &str = "app_api_tokens";
+const ROLES_TABLE: &str = "app_user_roles";
+const TOKEN_PREFIX: &str = "dt_";
+
+// routes reachable without a user (the probes of systemd and load balancers included)
+const PUBLIC_PATHS: [&str; 3] = ["/login", "/healthz", "/readyz"];
+
+lazy_static! {
+    // unknown users are checked against this hash , so a login takes the same time whether the user exists or not
+    static ref DUMMY_PASSWORD_HASH: String = hash_password("dummy password").unwrap_or_default();
+}
+
+// where users and API tokens are looked up , one implementation per user store
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    // the user , when the password is right and the user is active
+    async fn verify_password(&self, username: &str, password: &str) -> Result<Option<AuthUser>, CustomError>;
+    // the owner of the token , when it is known , not expired and its user is active
+    async fn verify_token(&self, token: &str) -> Result<Option<AuthUser>, CustomError>;
+    async fn create_token(&self, user: &AuthUser, name: &str, expires_at: Option<DateTime<Utc>>) -> Result<String, CustomError>;
+}
+
+// the session cookie is Secure by default only behind a reverse proxy (PROXY.TRUST_FORWARDED) , the server itself speaks plain http
+pub fn load_auth_config(proxy: &ProxyConfig) -> Result<AuthConfig, CustomError> {
+    let enabled = match get_optional_env_var::<String>("AUTH.MODE")?.as_deref() {
+        None | Some("local") => true,
+        Some("none") => false,
+        Some(mode) => return Err(CustomError::ConfigError(format!("invalid AUTH.MODE ({}) , valid values : 'local' , 'none'", mode)))
+    };
+
+    let config = AuthConfig {
+        enabled,
+        pool: get_optional_env_var::<String>("AUTH.POOL")?.unwrap_or_else(|| "default".to_string()),
+        schema: get_optional_env_var::<String>("AUTH.SCHEMA")?.unwrap_or_else(|| "public".to_string()),
+        session_ttl: Duration::from_secs(get_optional_env_var::<u64>("AUTH.SESSION_TTL_SECS")?.unwrap_or(8 * 60 * 60)),
+        cookie_secure: get_optional_env_var::<bool>("AUTH.COOKIE_SECURE")?.unwrap_or(proxy.trust_forwarded),
+    };
+
+    if config.enabled {
+        info!("auth : local users in pool ({}) , schema ({}) , session_ttl ({}s)", config.pool, config.schema, config.session_ttl.as_secs());
+    } else {
+        warn!("AUTH.MODE is 'none' , every route is open to anybody who can reach the server");
+    }
+
+    Ok(config)
+}
+
+// the users table has to exist at startup , the pool is created when no table of the registry uses it
+pub async fn make_auth(config: AuthConfig, pools: &mut DbPools) -> Result<Auth, CustomError> {
+    let backend: Option<Arc<dyn AuthBackend>> = if config.enabled {
+        if !pools.pools.contains_key(&config.pool) {
+            let pool = make_db_pool(config.pool.as_str()).await?;
+            pools.pools.insert(config.pool.to_string(), pool);
+        }
+        let pool = pools.pools[&config.pool].clone();
+        let backend = PgAuthBackend { pool, schema: config.schema.to_string() };
+        backend.check_tables().await?;
+        Some(Arc::new(backend))
+    } else {
+        None
+    };
+
+    Ok(Auth {
+        config,
+        backend,
+        sessions: Mutex::new(HashMap::new()),
+    })
+}
+
+/* ************************************************************************************* */
+
+pub fn hash_password(password: &str) -> Result<String, CustomError> {
+    let salt = match SaltString::encode_b64(Uuid::new_v4().as_bytes()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("could not make a password salt : {}", e);
+            return Err(CustomError::ConfigError("could not hash the password".to_string()))
+        }
+    };
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => {
+            error!("could not hash the password : {}", e);
+            Err(CustomError::ConfigError("could not hash the password".to_string()))
+        }
+    }
+}
+
+// argon2 is slow on purpose , it runs on the blocking thread pool
+async fn verify_password_hash(password: &str, password_hash: &str) -> bool {
+    let password = password.to_string();
+    let password_hash = password_hash.to_string();
+    let verified = web::block(move || match PasswordHash::new(password_hash.as_str()) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(e) => {
+            error!("invalid password hash : {}", e);
+            false
+        }
+    }).await;
+    verified.unwrap_or(false)
+}
+
+// only the hash of a token is stored , tokens are random so a fast hash is enough
+fn get_token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn make_random_secret() -> String {
+    format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple())
+}
+
+struct PgAuthBackend {
+    pool: Pool,
+    schema: String,
+}
+
+impl PgAuthBackend {
+    fn get_table(&self, table: &str) -> String {
+        format!("{}.{}", quote_identifier(self.schema.as_str()), quote_identifier(table))
+    }
+
+    // roles of the user 'u' of the query , as text[]
+    fn get_roles_select(&self) -> String {
+        format!("ARRAY(SELECT r.role::text FROM {} r WHERE r.username = u.username ORDER BY r.role)", self.get_table(ROLES_TABLE))
+    }
+
+    async fn get_client(&self) -> Result<deadpool_postgres::Client, CustomError> {
+        match self.pool.get().await {
+            Ok(c) => Ok(c),
+            Err(e) => {
+                error!("could not get a db connection : {}", e);
+                Err(CustomError::DatabaseError("database is unavailable, please try again".to_string()))
+            }
+        }
+    }
+
+    async fn check_tables(&self) -> Result<(), CustomError> {
+        let client = self.get_client().await?;
+        for table in [USERS_TABLE, TOKENS_TABLE, ROLES_TABLE] {
+            let sql_query = format!("SELECT 1 FROM {} LIMIT 0", self.get_table(table));
+            if let Err(e) = client.query(sql_query.as_str(), &[]).await {
+                error!("auth table ({}) is not readable : {}", self.get_table(table), e);
+                return Err(CustomError::ConfigError(format!("auth table ({}) does not exist in schema ({})", table, self.schema)))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthBackend for PgAuthBackend {
+    async fn verify_password(&self, username: &str, password: &str) -> Result<Option<AuthUser>, CustomError> {
+        let client = self.get_client().await?;
+        let sql_query = format!("SELECT u.password_hash , {} FROM {} u WHERE u.username = $1 AND u.is_active",
+                                self.get_roles_select(), self.get_table(USERS_TABLE));
+        let user: Option<(String, Vec<String>)> = match client.query_opt(sql_query.as_str(), &[&username]).await {
+            Ok(row) => row.map(|r| (r.get(0), r.get(1))),
+            Err(e) => {
+                error!("could not read user ({}) : {}", username, e);
+                return Err(CustomError::DatabaseError("could not check the password".to_string()))
+            }
+        };
+
+        let password_hash = user.as_ref().map_or(DUMMY_PASSWORD_HASH.as_str(), |(h, _)| h.as_str());
+        let verified = verify_password_hash(password, password_hash).await;
+        match user {
+            Some((_, roles)) if verified => Ok(Some(AuthUser { username: username.to_string(), roles })),
+            _ => Ok(None),
+        }
+    }
+
+    async fn verify_token(&self, token: &str) -> Result<Option<AuthUser>, CustomError> {
+        let client = self.get_client().await?;
+        let sql_query = format!("SELECT u.username , {} FROM {} t JOIN {} u ON u.username = t.username \
+                                 WHERE t.token_hash = $1 AND u.is_active AND (t.expires_at IS NULL OR t.expires_at > now())",
+                                self.get_roles_select(), self.get_table(TOKENS_TABLE), self.get_table(USERS_TABLE));
+        match client.query_opt(sql_query.as_str(), &[&get_token_hash(token)]).await {
+            Ok(row) => Ok(row.map(|r| AuthUser { username: r.get(0), roles: r.get(1) })),
+            Err(e) => {
+                error!("could not read API token : {}", e);
+                Err(CustomError::DatabaseError("could not check the API token".to_string()))
+            }
+        }
+    }
+
+    async fn create_token(&self, user: &AuthUser, name: &str, expires_at: Option<DateTime<Utc>>) -> Result<String, CustomError> {
+        let client = self.get_client().await?;
+        let token = format!("{}{}", TOKEN_PREFIX, make_random_secret());
+        let sql_query = format!("INSERT INTO {} (token_hash, username, name, expires_at) VALUES ($1, $2, $3, $4)", self.get_table(TOKENS_TABLE));
+        match client.execute(sql_query.as_str(), &[&get_token_hash(token.as_str()), &user.username, &name, &expires_at]).await {
+            Ok(_) => Ok(token),
+            Err(e) => {
+                error!("could not create API token for ({}) : {}", user.username, e);
+                Err(CustomError::DatabaseError("could not create the API token".to_string()))
+            }
+        }
+    }
+}
+
+/* ************************************************************************************* */
+
+// every change of the sessions is a single insert , remove or retain : a panic under the lock cannot leave the map
+// half updated , so a poisoned lock is taken over instead of locking everybody out
+fn lock_sessions(auth: &Auth) -> MutexGuard<'_, HashMap<String, AuthSession>> {
+    match auth.sessions.lock() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("sessions lock is poisoned : {}", e);
+            e.into_inner()
+        }
+    }
+}
+
+fn get_session_user(auth: &Auth, session_id: &str) -> Option<AuthUser> {
+    let mut sessions = lock_sessions(auth);
+    match sessions.get(session_id) {
+        Some(session) if session.expires_at > Instant::now() => Some(AuthUser { username: session.username.to_string(), roles: session.roles.clone() }),
+        Some(_) => {
+            sessions.remove(session_id);
+            None
+        },
+        None => None,
+    }
+}
+
+async fn get_request_user(auth: &Auth, backend: &Arc<dyn AuthBackend>, req: &ServiceRequest) -> Result<Option<AuthUser>, CustomError> {
+    let bearer = req.headers().get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string());
+    if let Some(token) = bearer {
+        return backend.verify_token(token.as_str()).await
+    }
+
+    Ok(req.cookie(SESSION_COOKIE).and_then(|c| get_session_user(auth, c.value())))
+}
+
+// the user is stored in the extensions of the request (see get_current_user)
+// without a user : pages of the UI redirect to the login page , everything else is a 401
+pub async fn with_auth(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let auth = match req.app_data::<web::Data<Auth>>() {
+        Some(a) => a.clone(),
+        None => return Err(CustomError::ConfigError("authentication is not configured".to_string()).into())
+    };
+    let backend = match &auth.backend {
+        Some(b) if !PUBLIC_PATHS.contains(&req.path()) => b.clone(),
+        _ => return Ok(next.call(req).await?.map_into_boxed_body()),
+    };
+
+    let user = match get_request_user(&auth, &backend, &req).await {
+        Ok(u) => u,
+        Err(e) => return Ok(req.into_response(e.error_response()))
+    };
+
+    match user {
+        Some(user) => {
+            Span::current().record("user", user.username.as_str());
+            req.extensions_mut().insert(user);
+            Ok(next.call(req).await?.map_into_boxed_body())
+        },
+        None => {
+            let wants_html = req.method() == actix_web::http::Method::GET && req.headers().get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("text/html"));
+            if wants_html {
+                // back to the same page , query string included , once logged in
+                let next = req.uri().path_and_query().map_or(req.path(), |p| p.as_str());
+                let location = format!("/login?next={}", percent_encode(next));
+                let response = HttpResponse::SeeOther().insert_header((header::LOCATION, location)).finish();
+                return Ok(req.into_response(response))
+            }
+            // the error body is built (and logged) here , inside the scope of the request id
+            let response = CustomError::Unauthorized("please log in or send an API token".to_string()).error_response();
+            Ok(req.into_response(response))
+        }
+    }
+}
+
+pub fn get_current_user(req: &HttpRequest) -> Option<AuthUser> {
+    req.extensions().get::<AuthUser>().cloned()
+}
+
+/* ************************************************************************************* */
+
+// only pages of this site , '//host' would leave it
+pub fn get_safe_next(next: &str) -> String {
+    if next.starts_with('/') && !next.starts_with("//") && !next.contains('\\') {
+        next.to_string()
+    } else {
+        "/tables".to_string()
+    }
+}
+
+// a new session , returned as the cookie to set
+pub async fn login(auth: &Auth, username: &str, password: &str) -> Result<Cookie<'static>, CustomError> {
+    let backend = match &auth.backend {
+        Some(b) => b,
+        None => return Err(CustomError::ValidationError("authentication is disabled".to_string()))
+    };
+
+    let user = match backend.verify_password(username, password).await? {
+        Some(u) => u,
+        None => {
+            warn!("failed login for ({})", username);
+            return Err(CustomError::Unauthorized("invalid username or password".to_string()))
+        }
+    };
+
+    let session_id = make_random_secret();
+    {
+        let mut sessions = lock_sessions(auth);
+        let now = Instant::now();
+        sessions.retain(|_, s| s.expires_at > now);
+        sessions.insert(session_id.to_string(), AuthSession {
+            username: user.username.to_string(),
+            roles: user.roles.clone(),
+            expires_at: now + auth.config.session_ttl,
+        });
+    }
+    info!("login : ({}) , roles : {:?}", user.username, user.roles);
+
+    Ok(Cookie::build(SESSION_COOKIE, session_id)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(auth.config.cookie_secure)
+        .max_age(actix_web::cookie::time::Duration::seconds(auth.config.session_ttl.as_secs() as i64))
+        .finish())
+}
+
+pub fn logout(auth: &Auth, req: &HttpRequest) {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        if let Some(session) = lock_sessions(auth).remove(cookie.value()) {
+            info!("logout : ({})", session.username);
+        }
+    }
+}
+
+pub async fn create_api_token(auth: &Auth, user: Option<AuthUser>, request: &ApiTokenRequest) -> Result<ApiTokenResponse, CustomError> {
+    let (backend, user) = match (&auth.backend, user) {
+        (Some(b), Some(u)) => (b, u),
+        _ => return Err(CustomError::ValidationError("authentication is disabled".to_string()))
+    };
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(CustomError::ValidationError("the API token needs a name".to_string()))
+    }
+    let expires_at = match request.ttl_days {
+        Some(days) if !(1..=3650).contains(&days) => return Err(CustomError::ValidationError("ttl_days must be between 1 and 3650".to_string())),
+        Some(days) => Some(Utc::now() + chrono::Duration::days(days)),
+        None => None,
+    };
+
+    let token = backend.create_token(&user, name, expires_at).await?;
+    info!("API token ({}) created for ({})", name, user.username);
+    Ok(ApiTokenResponse {
+        token,
+        name: name.to_string(),
+        username: user.username,
+        expires_at,
+    })
+}
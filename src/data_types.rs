@@ -1,64 +1,146 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI64};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::error::Error as StdError;
-use std::fmt::{Display, Formatter};
 use actix_web::{HttpResponse, ResponseError};
-use serde_json::Error as SerdeError;
-use derive_more::{Display, From};
+use actix_web::http::StatusCode;
+use deadpool_postgres::Pool;
+use tokio_postgres::CancelToken;
+use derive_more::Display;
+use tracing::{debug, error, warn};
+use crate::auth_ops::AuthBackend;
+use crate::audit_ops::AuditSink;
+use crate::request_ops::get_request_id;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct TRandom {
-    pub random_num: i32,
-    pub random_float: f64,
-    pub md5: String,
+// ----------------------- table registry -----------------------
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TableRegistry {
+    pub tables: Vec<TableConfig>,
 }
 
-#[derive(sqlx::FromRow)]
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Data1 {
-    // Define your data structure
-    pub random_num: i32,
-    pub random_float: f64,
-    pub md5: String,
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TableConfig {
+    pub short_name: String,      // name used by the UI >> examples : 'table1' , 'table2'
+    pub title: String,           // header shown above the table in the UI
+    #[serde(default = "default_schema")]
+    pub schema: String,          // postgres schema >> default : 'public'
+    pub backend_table: String,   // actual postgres table >> examples : 't_random' , 't_data'
+    #[serde(default = "default_pool")]
+    pub pool: String,            // logical database the table lives in >> default : 'default'
+    #[serde(default)]
+    pub columns: Vec<String>,    // visible columns, in the order they are displayed (all columns when omitted)
+    #[serde(default)]
+    pub count_estimate_threshold: Option<i64>,  // above this many rows, recordsTotal comes from pg_class.reltuples
+    #[serde(default)]
+    pub fulltext: Option<FullTextConfig>,  // 'fulltext' search mode (optional : 'simple' over the text columns)
+    #[serde(default)]
+    pub keyset: Option<KeysetConfig>,      // keyset (cursor) pagination for deep pages (optional)
+    #[serde(default)]
+    pub access: Vec<AccessRule>,           // roles allowed to use the table (optional : every user when omitted)
+    #[serde(skip_deserializing)]
+    pub column_info: Vec<ColumnInfo>,  // read from information_schema at startup, same order as 'columns'
+    #[serde(skip_deserializing)]
+    pub has_pg_trgm: bool,             // pg_trgm is installed in the database of the table ('fuzzy' search mode)
+    #[serde(skip_deserializing)]
+    pub row_filter: Option<String>,    // view of a user (see access_ops.rs) : rows they may see , AND-ed into every WHERE clause
+    #[serde(skip_deserializing)]
+    pub permissions: Vec<Permission>,  // view of a user : what they may do with the table
 }
 
-#[derive(sqlx::FromRow)]
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Data2 {
-    // Define your data structure
-    pub my_date: String,
-    pub my_data: String,
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FullTextConfig {
+    #[serde(default = "default_fulltext_language")]
+    pub language: String,        // text search configuration >> examples : 'english' , 'simple' (default)
+    #[serde(default)]
+    pub columns: Vec<String>,    // columns of the searched document (the text columns when omitted)
+    #[serde(default)]
+    pub order_by_rank: bool,     // best matches (ts_rank) first , before the order chosen in the UI
+    #[serde(default)]
+    pub headline: bool,          // return a '_headline' snippet (ts_headline) with every row
 }
 
+impl Default for FullTextConfig {
+    fn default() -> Self {
+        FullTextConfig {
+            language: default_fulltext_language(),
+            columns: vec![],
+            order_by_rank: false,
+            headline: false,
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PaginationParams {
-    pub page: u32,
-    pub page_size: u32,
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct KeysetConfig {
+    pub column: String,          // unique , not null column , the tie breaker of every sort >> example : 'id'
+    #[serde(default = "default_keyset_min_rows")]
+    pub min_rows: i64,           // below this many (filtered) rows pages keep using OFFSET >> default : 10000
 }
 
-// -----------------------
-#[derive(Debug)]
-pub enum GenericError {
-    InvalidInput,
+// a role of the users (app_user_roles) and what it may do with the table
+// export includes view , admin (exports of the table in /admin/exports) includes export
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    pub role: String,
+    pub permissions: Vec<Permission>,
+    #[serde(default)]
+    pub row_filter: Option<String>,      // SQL predicate on the table >> example : 'region = ''emea''' (all rows when omitted)
+    #[serde(default)]
+    pub masked_columns: Vec<String>,     // read as '****' , they cannot be searched or sorted on
 }
 
-#[derive(Debug)]
-pub struct CustomErrorType {
-    pub err_type: GenericError,
-    pub err_msg: String
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    View,
+    Export,
+    Admin,
 }
 
-// -----------------------
+impl Permission {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Permission::View => "view",
+            Permission::Export => "export",
+            Permission::Admin => "admin",
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct QueryParams {
-    // pagination: PaginationParams,
-    // page: u32,
-    // page_size: u32,
-    pub start: u32,
-    pub length: u32,
-    pub draw: u32,
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub data_type: String,   // information_schema data_type >> examples : 'integer' , 'text' , 'ARRAY'
+    pub udt_name: String,    // postgres type name >> examples : 'int4' , 'text' , '_int4'
+    pub is_nullable: bool,
+    #[serde(default)]
+    pub masked: bool,        // masked for the current user (see access_ops.rs)
+}
+
+// postgres pools keyed by logical database (the 'pool' of a table in the registry)
+#[derive(Clone)]
+pub struct DbPools {
+    pub pools: HashMap<String, Pool>,
+}
+
+fn default_schema() -> String {
+    "public".to_string()
+}
+
+fn default_pool() -> String {
+    "default".to_string()
+}
+
+fn default_fulltext_language() -> String {
+    "simple".to_string()
+}
+
+fn default_keyset_min_rows() -> i64 {
+    10000
 }
 
 #[derive(Deserialize)]
@@ -71,59 +153,674 @@ pub struct FormData {
 
 #[derive(Deserialize, Serialize)]
 pub struct ExportData {
-    pub search_string: String,  // values : any search string >> examples : 'xyz' (or) '1234' (or) 'xyz | 123' (or) 'xxx + yyy'
+    pub search_string: String,  // search expression >> examples : 'xyz' (or) 'xyz | 123' (or) '(xxx + yyy) -zzz' (or) 'random_num:>500'
     pub table_name: String,     // valid values >> 'table1'
-    pub pattern_match: String,  // valid values >> 'like' | 'exact'
+    pub pattern_match: String,  // valid values >> 'like' | 'exact' | 'fulltext' | 'fuzzy' | 'regex'
+    #[serde(default)]
+    pub format: ExportFormat,   // valid values >> 'csv' (default) | 'tsv' | 'jsonl' | 'xlsx' | 'parquet'
+    #[serde(default)]
+    pub column_searches: Vec<ExportColumnSearch>,   // per-column filters of the UI , AND-ed with the search
 }
 
-
+// same syntax as the search , compared with 'pattern_match' of the export
 #[derive(Deserialize, Serialize)]
-pub struct SearchStringData {
-    pub search_string: Vec<String>,  // a vector of search strings
-    pub search_type: String,         // valid values >> 'and' | 'or'
+pub struct ExportColumnSearch {
+    pub column_name: String,
+    pub search_string: String,
 }
 
+
+// server-side processing response expected by DataTables 1.10
 #[derive(Serialize)]
-pub struct JsonResponse {
-    pub message: String,
-    pub status: u32,
+pub struct DataTablesResponse {
+    pub draw: u32,
+    #[serde(rename = "recordsTotal")]
+    pub records_total: i64,
+    #[serde(rename = "recordsFiltered")]
+    pub records_filtered: i64,
+    pub data: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,   // keyset pagination : send back as 'cursor' with the 'start' of the next page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,   // same , for the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scores: Option<Vec<serde_json::Value>>,  // fulltext / fuzzy search : the scores of every row of 'data' (see page_ops)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+// ----------------------- search expressions (see search_ops.rs) -----------------------
+
+// examples : 'xyz' , 'xxx + yyy' , '(abc | "a phrase") -xyz' , 'random_num:>500' , 'my_date:2022-01..2022-06'
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchExpr {
+    And(Vec<SearchExpr>),
+    Or(Vec<SearchExpr>),
+    Not(Box<SearchExpr>),
+    Term(SearchTerm),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchTerm {
+    pub column: Option<String>,  // 'column:value' >> only that column , None >> every searchable column
+    pub op: SearchOp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchOp {
+    Match(String),                          // compared according to pattern_match ('exact' | 'like')
+    Compare(CompareOp, String),             // random_num:>500
+    Range(Option<String>, Option<String>),  // my_date:2022-01..2022-06 , bounds are inclusive and optional
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+// ----------------------- DataTables server-side request -----------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct DataTablesRequest {
+    pub draw: u32,
+    pub start: u32,
+    pub length: u32,
+    pub search_value: String,            // search[value] >> the global search box
+    pub exact_search: String,            // exactsearch >> 'true' | 'false'
+    pub search_mode: String,             // searchmode >> 'exact' | 'like' | 'fulltext' | 'fuzzy' | 'regex' (overrides exactsearch when set)
+    pub table_name: String,              // tablename >> short name from the registry
+    pub cursor: String,                  // cursor >> 'next_cursor' / 'prev_cursor' of the previous response (optional)
+    pub columns: Vec<DataTablesColumn>,  // columns[i][...] , in column index order
+    pub order: Vec<DataTablesOrder>,     // order[n][...] , in priority order
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DataTablesColumn {
+    pub index: usize,
+    pub data: String,
+    pub searchable: bool,
+    pub orderable: bool,
+    pub search_value: String,   // columns[i][search][value] >> per-column filter
+    pub exact_search: String,   // columns[i][search][exact] >> 'true' | 'false' , empty means same as exactsearch
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DataTablesOrder {
+    pub column: usize,
+    pub dir: String,            // 'asc' | 'desc'
+}
+
+// the rows of one page , for /query and /api/v1/tables/{name}/rows (see page_ops.rs)
+#[derive(Debug, Clone, Default)]
+pub struct PageRequest {
+    pub start: u32,
+    pub length: u32,
+    pub cursor: String,          // keyset pagination , empty >> OFFSET
+}
+
+#[derive(Debug, Default)]
+pub struct TablePage {
+    pub rows: Vec<serde_json::Value>,
+    pub records_total: i64,
+    pub records_filtered: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+    pub scores: Option<Vec<serde_json::Value>>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortColumn {
+    pub name: String,
+    pub descending: bool,
+}
+
+// keyset pagination : position of a page , handed to the UI as an opaque (hex) token
+// the page is only read from the cursor when start , length , search and sort are the same (fingerprint)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeysetCursor {
+    pub start: u32,
+    pub length: u32,
+    pub fingerprint: String,
+    pub values: Vec<String>,     // sort values (as text) of the row the page starts after (or ends before)
+    pub backward: bool,          // previous page >> the rows before the values
+}
+
+// everything that ends up in the WHERE clause
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    pub search: Option<SearchExpr>,          // global search
+    pub pattern_match: String,               // 'exact' | 'like' | 'fulltext' | 'fuzzy' | 'regex' , for the global search
+    pub columns: Vec<ColumnInfo>,            // every column of the table ('column:value' terms and their types)
+    pub fulltext: FullTextConfig,            // language and columns of the 'fulltext' mode
+    pub searchable_columns: Vec<String>,     // columns the global search looks into
+    pub column_searches: Vec<ColumnSearch>,  // per-column filters (AND-ed with the global search)
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnSearch {
+    pub column_name: String,
+    pub search: SearchExpr,
+    pub pattern_match: String,               // 'exact' | 'like' | 'fulltext' | 'fuzzy' | 'regex'
+}
+
+// status of an export job (POST /export_csv , GET /exports/{id} , DELETE /exports/{id})
+// message : download link once completed , error otherwise
+// rows / time_taken_for_export : progress so far while the job is running
 #[derive(Serialize)]
 pub struct JsonResponseWithCSVExportData {
+    pub job_id: String,
+    pub table_name: String,
+    pub format: ExportFormat,
+    pub state: ExportState,
     pub message: String,
     pub status: u32,
-    pub rows: i32,
+    pub rows: i64,
     pub time_taken_for_export: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ExportResults {
-    pub csv_file_path: String,
-    pub rows: i32,
+    pub file_name: String,
+    pub file_path: String,
+    pub content_type: String,
+    pub size_bytes: u64,
+    pub rows: i64,
     pub time_taken_for_export: f64,
 }
 
+// ----------------------- export jobs -----------------------
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Tsv,
+    Jsonl,      // JSON Lines (NDJSON)
+    Xlsx,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Tsv => "tsv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Xlsx => "xlsx",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Tsv => "text/tab-separated-values",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
 
+    // used for exports written before the format was recorded in their metadata
+    pub fn from_extension(extension: &str) -> Option<ExportFormat> {
+        match extension {
+            "csv" => Some(ExportFormat::Csv),
+            "tsv" => Some(ExportFormat::Tsv),
+            "jsonl" => Some(ExportFormat::Jsonl),
+            "xlsx" => Some(ExportFormat::Xlsx),
+            "parquet" => Some(ExportFormat::Parquet),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+// shared between the export task and the handlers
+#[derive(Default)]
+pub struct ExportProgress {
+    pub rows: AtomicI64,                            // rows written so far
+    pub cancelled: AtomicBool,                      // set by DELETE /exports/{id}
+    pub cancel_token: Mutex<Option<CancelToken>>,   // cancels the query running on postgres
+}
+
+pub struct ExportJob {
+    pub table_name: String,
+    pub format: ExportFormat,
+    pub created_by: String,
+    pub state: ExportState,
+    pub started_at: Instant,
+    pub finished_at: Option<Instant>,
+    pub progress: Arc<ExportProgress>,
+    pub results: Option<ExportResults>,
+    pub error: Option<String>,
+}
+
+// jobs of all the workers , keyed by job id
+#[derive(Default)]
+pub struct ExportJobs {
+    pub jobs: Mutex<HashMap<String, ExportJob>>,
+}
+
+// ----------------------- export retention -----------------------
+
+// EXPORT.TTL_SECS , EXPORT.MAX_TOTAL_MB , EXPORT.SWEEP_INTERVAL_SECS , EXPORT.ADMIN_ROLE
+#[derive(Debug, Clone)]
+pub struct ExportRetention {
+    pub ttl: Duration,                      // exports are deleted this long after they were created
+    pub max_total_bytes: Option<u64>,       // quota of data_dir , oldest exports are deleted first
+    pub sweep_interval: Duration,
+    pub admin_role: String,                 // manages every export in /admin/exports (see access_ops)
+}
+
+// sidecar of every export >> data_dir/<file_name>.meta.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportMetadata {
+    pub file_name: String,
+    pub table_name: String,
+    pub format: Option<ExportFormat>,       // None for files exported before metadata was recorded
+    pub rows: Option<i64>,
+    pub size_bytes: u64,
+    pub created_by: String,                 // user , or client address without authentication
+    pub request_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// GET /admin/exports
+#[derive(Serialize)]
+pub struct ExportListResponse {
+    pub exports: Vec<ExportFileInfo>,
+    pub total_bytes: u64,
+    pub max_total_bytes: Option<u64>,
+    pub ttl_secs: u64,
+}
+
+#[derive(Serialize)]
+pub struct ExportFileInfo {
+    #[serde(flatten)]
+    pub metadata: ExportMetadata,
+    pub expires_at: DateTime<Utc>,
+}
+
+// DELETE /admin/exports
+#[derive(Deserialize)]
+pub struct ExportPurgeParams {
+    pub older_than_secs: Option<u64>,      // all exports when omitted
+    pub table_name: Option<String>,
+}
 
-#[derive(Display, From, Debug)]
+#[derive(Serialize)]
+pub struct ExportPurgeResponse {
+    pub deleted: Vec<String>,
+    pub failed: Vec<String>,        // the exports that could not be deleted
+    pub freed_bytes: u64,
+}
+
+// download links are signed with 'secret' and valid for 'link_ttl'
+#[derive(Clone)]
+pub struct DownloadConfig {
+    pub secret: Vec<u8>,
+    pub link_ttl: Duration,
+}
+
+// GET /downloads/{file_name}?expires=1700000000&sig=...
+#[derive(Deserialize)]
+pub struct DownloadParams {
+    pub expires: i64,
+    pub sig: String,
+}
+
+
+
+// every error returned by the handlers , the message is shown to the user (it never contains SQL or
+// postgres internals, those are only logged)
+#[derive(Display, Debug)]
 pub enum CustomError {
-    DatabaseError,
-    InvalidData,
-    QueryError,
-    InvalidTable,
+    #[display(fmt = "{}", _0)]
+    ValidationError(String),    // bad form field / payload / search string
+    #[display(fmt = "{}", _0)]
+    InvalidTable(String),       // table is not in the registry
+    #[display(fmt = "{}", _0)]
+    NotFound(String),           // unknown export job
+    #[display(fmt = "{}", _0)]
+    Unauthorized(String),       // no (valid) session or API token
+    #[display(fmt = "{}", _0)]
+    Forbidden(String),          // invalid or expired download link
+    #[display(fmt = "{}", _0)]
+    DatabaseError(String),      // pool / connection / query failures
+    #[display(fmt = "{}", _0)]
+    ExportError(String),        // export file could not be written
+    #[display(fmt = "{}", _0)]
+    QuotaExceeded(String),      // data_dir is above EXPORT.MAX_TOTAL_MB
+    #[display(fmt = "{}", _0)]
+    ConfigError(String),        // env config , table registry , templates
 }
 
+impl CustomError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CustomError::ValidationError(_) => "validation_error",
+            CustomError::InvalidTable(_) => "invalid_table",
+            CustomError::NotFound(_) => "not_found",
+            CustomError::Unauthorized(_) => "unauthorized",
+            CustomError::Forbidden(_) => "forbidden",
+            CustomError::DatabaseError(_) => "database_error",
+            CustomError::ExportError(_) => "export_error",
+            CustomError::QuotaExceeded(_) => "quota_exceeded",
+            CustomError::ConfigError(_) => "config_error",
+        }
+    }
+
+    // 5xx : error , 4xx : warn
+    // validation errors may quote the search , their message is only logged at 'debug'
+    pub fn log(&self) {
+        match self {
+            CustomError::ValidationError(msg) => {
+                warn!(code = self.code(), "invalid request");
+                debug!("{}", msg);
+            },
+            _ if self.status_code().is_server_error() => error!(code = self.code(), "{}", self),
+            _ => warn!(code = self.code(), "{}", self),
+        }
+    }
+}
 
 impl std::error::Error for CustomError {}
 
+// JSON body of every error response >> { "code": "validation_error", "message": "...", "request_id": "..." }
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    pub request_id: String,
+}
+
 impl ResponseError for CustomError {
-    fn error_response(&self) -> HttpResponse {
-        match *self {
-            CustomError::DatabaseError => HttpResponse::InternalServerError().finish(),
-            CustomError::InvalidData => HttpResponse::BadRequest().finish(),
-            CustomError::QueryError => HttpResponse::BadRequest().finish(),
-            CustomError::InvalidTable => HttpResponse::BadRequest().finish(),
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CustomError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            CustomError::InvalidTable(_) => StatusCode::BAD_REQUEST,
+            CustomError::NotFound(_) => StatusCode::NOT_FOUND,
+            CustomError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            CustomError::Forbidden(_) => StatusCode::FORBIDDEN,
+            CustomError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CustomError::ExportError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CustomError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            CustomError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let request_id = get_request_id();
+        self.log();
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            request_id,
+        })
+    }
+}
+
+// ----------------------- JSON API (/api/v1) -----------------------
+
+#[derive(Serialize)]
+pub struct ApiTablesResponse {
+    pub tables: Vec<ApiTableSummary>,
+}
+
+#[derive(Serialize)]
+pub struct ApiTableSummary {
+    pub name: String,
+    pub title: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ApiTableSchema {
+    pub name: String,
+    pub title: String,
+    pub columns: Vec<ColumnInfo>,
+    pub pattern_matches: Vec<String>,        // search modes available for the table
+    pub fulltext_columns: Vec<String>,       // columns of the 'fulltext' document
+    pub key_column: Option<String>,          // keyset pagination ('cursor') when set
+    pub permissions: Vec<Permission>,        // what the caller may do with the table
+}
+
+// POST /api/v1/tables/{name}/rows , every field is optional
+// {"filters": [{"column": "random_num", "op": "gt", "value": 500}], "sort": [{"column": "md5", "dir": "desc"}],
+//  "columns": ["random_num", "md5"], "limit": 100, "offset": 0}
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ApiRowsRequest {
+    #[serde(default)]
+    pub search: String,                      // search expression , same syntax as the search box
+    #[serde(default = "default_api_pattern_match")]
+    pub pattern_match: String,               // 'like' (default) | 'exact' | 'fulltext' | 'fuzzy' | 'regex'
+    #[serde(default)]
+    pub filters: Vec<ApiFilter>,             // AND-ed together and with the search
+    #[serde(default)]
+    pub sort: Vec<ApiSort>,
+    #[serde(default)]
+    pub columns: Vec<String>,                // projection , all the columns of the table when empty
+    #[serde(default = "default_api_limit")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+    #[serde(default)]
+    pub cursor: String,                      // 'next_cursor' / 'prev_cursor' of the previous response (with its offset)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ApiFilter {
+    pub column: String,
+    pub op: String,                          // 'eq' | 'ne' | 'gt' | 'ge' | 'lt' | 'le' | 'between' | 'match'
+    pub value: serde_json::Value,            // typed value , [from, to] for 'between' (null >> open bound)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ApiSort {
+    pub column: String,
+    #[serde(default = "default_api_sort_dir")]
+    pub dir: String,                         // 'asc' (default) | 'desc'
+}
+
+#[derive(Serialize)]
+pub struct ApiRowsResponse {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<serde_json::Value>,
+    pub total: i64,
+    pub filtered: i64,
+    pub offset: u32,
+    pub limit: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scores: Option<Vec<serde_json::Value>>,  // fulltext / fuzzy search : the scores of every row (see page_ops)
+}
+
+fn default_api_pattern_match() -> String {
+    "like".to_string()
+}
+
+fn default_api_limit() -> u32 {
+    100
+}
+
+fn default_api_sort_dir() -> String {
+    "asc".to_string()
+}
+
+// ----------------------- authentication (see auth_ops.rs) -----------------------
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub enabled: bool,           // AUTH.MODE >> 'local' (default) | 'none'
+    pub pool: String,            // AUTH.POOL >> pool of the users and API tokens tables , default : 'default'
+    pub schema: String,          // AUTH.SCHEMA >> schema of those tables , default : 'public'
+    pub session_ttl: Duration,   // AUTH.SESSION_TTL_SECS >> default : 8 hours
+    pub cookie_secure: bool,     // AUTH.COOKIE_SECURE >> session cookie only sent over https , default : the value of PROXY.TRUST_FORWARDED
+}
+
+// who is calling , stored in the extensions of the request by the auth middleware
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub username: String,
+    pub roles: Vec<String>,      // app_user_roles , matched with the 'access' rules of the registry
+}
+
+pub struct AuthSession {
+    pub username: String,
+    pub roles: Vec<String>,      // read at login
+    pub expires_at: Instant,
+}
+
+// the login sessions of the UI (in memory , a restart logs everybody out)
+pub struct Auth {
+    pub config: AuthConfig,
+    pub backend: Option<Arc<dyn AuthBackend>>,   // None when AUTH.MODE is 'none'
+    pub sessions: Mutex<HashMap<String, AuthSession>>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub next: String,            // page to go back to after the login
+}
+
+#[derive(Deserialize)]
+pub struct LoginParams {
+    #[serde(default)]
+    pub next: String,
+}
+
+// POST /auth/tokens >> {"name": "nightly-report", "ttl_days": 90}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub ttl_days: Option<i64>,   // no expiry when omitted
+}
+
+// the token is only shown once , the database only keeps its hash
+#[derive(Serialize)]
+pub struct ApiTokenResponse {
+    pub token: String,
+    pub name: String,
+    pub username: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// ----------------------- audit log (see audit_ops.rs) -----------------------
+
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub mode: String,            // AUDIT.MODE >> 'file' (default) | 'postgres' | 'none'
+    pub file: String,            // AUDIT.FILE >> JSONL file of the 'file' mode , default : 'audit_log.jsonl'
+    pub pool: String,            // AUDIT.POOL >> pool of the audit table ('postgres' mode) , default : 'default'
+    pub schema: String,          // AUDIT.SCHEMA >> schema of the audit table , default : 'public'
+    pub role: String,            // AUDIT.ROLE >> role allowed to browse the audit log (/audit) , default : 'auditor'
+}
+
+pub struct Audit {
+    pub config: AuditConfig,
+    pub sink: Option<Arc<dyn AuditSink>>,   // None when AUDIT.MODE is 'none'
+}
+
+// one line of the audit log : a /query page , a page of the JSON API or an export
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub created_at: DateTime<Utc>,
+    pub request_id: String,
+    pub username: String,                // user , or client address without authentication (same as 'created_by' of an export)
+    pub client_ip: String,
+    pub action: String,                  // 'query' | 'api_rows' | 'export'
+    pub table_name: String,
+    pub search: String,                  // search expression , column searches and API filters
+    pub pattern_match: String,
+    pub rows: Option<i64>,               // rows returned (query) or written (export)
+    pub duration_ms: i64,
+    pub export_file: Option<String>,
+    pub status: String,                  // 'ok' , 'cancelled' or the error code >> example : 'forbidden'
+}
+
+// an entry being filled while the request (or the export) runs
+pub struct AuditEvent {
+    pub sink: Option<Arc<dyn AuditSink>>,
+    pub entry: AuditEntry,
+    pub started_at: Instant,
+}
+
+// a page of the audit log , from the DataTables request of /audit/query
+#[derive(Debug, Clone, Default)]
+pub struct AuditPageRequest {
+    pub start: u32,
+    pub length: u32,
+    pub search: String,                  // matched (ILIKE) with the text of every column
+    pub sort: Option<SortColumn>,        // newest first when None
+}
+
+// ----------------------- logging -----------------------
+
+// PROXY.TRUST_FORWARDED=true : the app runs behind a reverse proxy that sets Forwarded / X-Forwarded-For
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    pub trust_forwarded: bool,
+}
+
+// the table of the request , in the extensions of the request once the table is known (see set_request_table)
+#[derive(Debug, Clone)]
+pub struct RequestTable(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    #[default]
+    Pretty,     // human readable , one line per event
+    Json,       // one JSON object per event , for log collectors
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub level: String,      // 'info' , or a filter >> example : 'info,tokio_postgres=warn'
+    pub format: LogFormat,
+}
+
+// ----------------------- health -----------------------
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthCheck {
+    pub name: String,                    // 'pool:default' | 'data_dir' | 'templates'
+    pub ok: bool,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// GET /readyz >> 200 'ready' , or 503 'not_ready' when a check failed
+#[derive(Serialize, Debug, Clone)]
+pub struct ReadinessResponse {
+    pub status: String,
+    pub checks: Vec<HealthCheck>,
 }
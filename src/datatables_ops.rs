@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use regex::Regex;
+use crate::data_types::{ColumnSearch, CustomError, DataTablesColumn, DataTablesOrder, DataTablesRequest, FormData, SearchFilters, TableConfig};
+use crate::api_ops::MAX_API_LIMIT;
+use crate::db_ops::get_table_column_mapping;
+use crate::fulltext_ops::get_fulltext_config;
+use crate::fuzzy_ops::check_fuzzy_search;
+use crate::search_ops::parse_search_expression;
+
+// reads the form fields sent by DataTables (server-side processing)
+// examples : 'columns[2][search][value]' , 'columns[0][orderable]' , 'order[1][dir]'
+pub async fn parse_datatables_request(form: &FormData) -> Result<DataTablesRequest, CustomError> {
+    let column_field = Regex::new(r"^columns\[(\d+)\]\[(\w+)\](?:\[(\w+)\])?$").unwrap();
+    let order_field = Regex::new(r"^order\[(\d+)\]\[(\w+)\]$").unwrap();
+
+    let mut request = DataTablesRequest::default();
+    let mut columns: BTreeMap<usize, DataTablesColumn> = BTreeMap::new();
+    let mut order: BTreeMap<usize, DataTablesOrder> = BTreeMap::new();
+
+    // Access all form fields dynamically
+    for (key, value) in &form.fields {
+        if key == "length" {
+            request.length = parse_page_length(value)?;
+        }
+        if key == "draw" {
+            request.draw = parse_number_field(key, value)?;
+        }
+        if key == "start" {
+            request.start = parse_number_field(key, value)?;
+        }
+
+        if key == "search[value]" {
+            request.search_value = value.to_string();
+        }
+
+        if key == "exactsearch" {
+            request.exact_search = value.to_string();
+        }
+
+        if key == "searchmode" {
+            request.search_mode = value.to_string();
+        }
+
+        if key == "tablename" {
+            request.table_name = value.to_string();
+        }
+
+        if key == "cursor" {
+            request.cursor = value.to_string();
+        }
+
+        // -------------- per-column settings | start ------------
+        if let Some(caps) = column_field.captures(key) {
+            let index = parse_number_field(key, &caps[1])?;
+            let column = columns.entry(index).or_insert_with(|| DataTablesColumn { index, ..Default::default() });
+            match (&caps[2], caps.get(3).map(|m| m.as_str())) {
+                ("data", None) => column.data = value.to_string(),
+                ("searchable", None) => column.searchable = value == "true",
+                ("orderable", None) => column.orderable = value == "true",
+                ("search", Some("value")) => column.search_value = value.to_string(),
+                ("search", Some("exact")) => column.exact_search = value.to_string(),
+                _ => (),
+            }
+        }
+        // -------------- per-column settings | end ------------
+
+        // -------------- column sorting | start ------------
+        if let Some(caps) = order_field.captures(key) {
+            let priority: usize = parse_number_field(key, &caps[1])?;
+            let entry = order.entry(priority).or_default();
+            match &caps[2] {
+                "column" => {
+                    entry.column = parse_number_field(key, value)?;
+                },
+                "dir" => entry.dir = value.to_string(), // 'asc' or 'desc'
+                _ => (),
+            }
+        }
+        // -------------- column sorting | end ------------
+    }
+
+    request.columns = columns.into_values().collect();
+    request.order = order.into_values().collect();
+
+    Ok(request)
+}
+
+fn parse_number_field<T: FromStr>(key: &str, value: &str) -> Result<T, CustomError> {
+    match value.trim().parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(CustomError::ValidationError(format!("invalid value ({}) for ({})", value, key)))
+    }
+}
+
+// same maximum as the JSON API , '-1' ('All' in the length menu of DataTables) is the maximum
+fn parse_page_length(value: &str) -> Result<u32, CustomError> {
+    match parse_number_field::<i64>("length", value)? {
+        -1 => Ok(MAX_API_LIMIT),
+        length if length < 0 => Err(CustomError::ValidationError(format!("invalid value ({}) for (length) , -1 or 0 to {}", value, MAX_API_LIMIT))),
+        length => Ok(length.min(MAX_API_LIMIT as i64) as u32),
+    }
+}
+
+/* ************************************************************************************* */
+
+pub async fn get_pattern_match(exact_search: &str) -> Result<String, CustomError> {
+    match exact_search {
+        "true" => Ok("exact".to_string()),
+        "false" => Ok("like".to_string()),
+        _ => Err(CustomError::ValidationError(format!("invalid exactsearch value ({})", exact_search)))
+    }
+}
+
+// global search (only over the 'searchable' columns) + per-column searches
+// when DataTables does not send any 'columns[i]' every column of the table is searchable
+pub async fn get_search_filters(table: &TableConfig, request: &DataTablesRequest) -> Result<SearchFilters, CustomError> {
+    let table_column_mapping = get_table_column_mapping(table).await;
+
+    // 'searchmode' (when sent) takes precedence over the 'exactsearch' checkbox
+    let pattern_match = match request.search_mode.as_str() {
+        "" => get_pattern_match(request.exact_search.as_str()).await?,
+        "exact" | "like" | "fulltext" | "fuzzy" | "regex" => request.search_mode.to_string(),
+        _ => return Err(CustomError::ValidationError(format!("invalid searchmode value ({})", request.search_mode)))
+    };
+    check_fuzzy_search(table, pattern_match.as_str())?;
+
+    let search = parse_search_expression(request.search_value.as_str(), &table.columns, pattern_match.as_str())?;
+
+    let mut searchable_columns = vec![];
+    let mut column_searches = vec![];
+
+    if request.columns.is_empty() {
+        searchable_columns = table.columns.clone();
+    }
+
+    for column in &request.columns {
+        let column_name = match table_column_mapping.get(&column.index.to_string()) {
+            Some(c) => c.to_string(),
+            None => return Err(CustomError::ValidationError(format!("invalid column index ({})", column.index)))
+        };
+
+        // masked columns (see access_ops) are not searched
+        if !column.searchable || table.column_info.iter().any(|c| c.name == column_name && c.masked) {
+            continue
+        }
+
+        searchable_columns.push(column_name.to_string());
+
+        let column_pattern_match = if column.exact_search.is_empty() {
+            pattern_match.to_string()
+        } else {
+            get_pattern_match(column.exact_search.as_str()).await?
+        };
+        if let Some(column_search) = parse_search_expression(column.search_value.as_str(), &table.columns, column_pattern_match.as_str())? {
+            column_searches.push(ColumnSearch {
+                column_name,
+                search: column_search,
+                pattern_match: column_pattern_match,
+            });
+        }
+    }
+
+    Ok(SearchFilters {
+        search,
+        pattern_match,
+        columns: table.column_info.clone(),
+        fulltext: get_fulltext_config(table),
+        searchable_columns,
+        column_searches,
+    })
+}
+
+// the global and per-column searches of the request , as recorded in the audit log
+// example : 'xyz | random_num:>500 ; md5: abc'
+pub fn get_search_description(table: &TableConfig, request: &DataTablesRequest) -> String {
+    let mut searches = vec![];
+    if !request.search_value.trim().is_empty() {
+        searches.push(request.search_value.trim().to_string());
+    }
+    for column in &request.columns {
+        if column.search_value.trim().is_empty() {
+            continue
+        }
+        let column_name = table.columns.get(column.index).map_or("-", |c| c.as_str());
+        searches.push(format!("{}: {}", column_name, column.search_value.trim()));
+    }
+    searches.join(" ; ")
+}
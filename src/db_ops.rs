@@ -1,430 +1,370 @@
 use std::collections::HashMap;
 use std::env;
-use deadpool_postgres::{Config, Pool};
-use dotenv::dotenv;
-use tokio_postgres::{NoTls, Error, Row};
-use crate::data_types::{CustomError, Data1, Data2, ExportResults};
-use csv::Writer;
-use std::fs::File;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use deadpool_postgres::{Config, Pool, PoolConfig, Runtime, Timeouts};
+use tracing::{debug, error, info};
+use crate::data_types::{ColumnInfo, CustomError, DbPools, ExportFormat, ExportMetadata, ExportProgress, ExportResults, TableConfig, TableRegistry};
+use crate::query_ops::{build_count_query, build_export_query, SqlQuery};
+use crate::access_ops::MASKED_VALUE;
+use crate::retention_ops::{remove_export_metadata, write_export_metadata, EXPORT_DIR, PARTIAL_EXPORT_SUFFIX};
+use crate::row_ops::{is_native_type, row_to_json_object};
+use crate::writer_ops::{make_export_writer, ExportWriter};
+use crate::string_ops::quote_identifier;
+use crate::log_ops::get_redacted_params;
+use futures::{pin_mut, StreamExt};
+use tokio_postgres::{Column, RowStream};
 use std::time::Instant;
-// use sqlx::FromRow;
+use chrono::Utc;
 use uuid::Uuid;
-use async_trait::async_trait;
 
-pub async fn make_db_pool() -> Pool {
-    dotenv().ok();
-    dotenv::from_filename("app.rust.env").ok();
-
-    let mut cfg = Config::new();
-    cfg.host = Option::from(env::var("PG.HOST").unwrap());
-    cfg.user = Option::from(env::var("PG.USER").unwrap());
-    cfg.password = Option::from(env::var("PG.PASSWORD").unwrap());
-    cfg.dbname = Option::from(env::var("PG.DBNAME").unwrap());
-    let pool: Pool = cfg.create_pool(None, tokio_postgres::NoTls).unwrap();
-    pool
+// env variables of the 'default' pool are PG.HOST, PG.USER ...
+// env variables of any other pool (e.g. 'reporting') are PG.REPORTING.HOST, PG.REPORTING.USER ...
+fn get_env_prefix_for_pool(pool_name: &str) -> String {
+    if pool_name == "default" {
+        "PG".to_string()
+    } else {
+        format!("PG.{}", pool_name.to_uppercase())
+    }
 }
 
-pub async fn get_table_columns(table_short_name: &str) -> Vec<String> {
-    let mut cols = vec![];
-    let mapping = get_table_column_mapping(table_short_name).await;
-    for(_, column_name) in &mapping {
-        cols.push(column_name.to_string());
+// optional env variable >> None when not set, error when set but not parsable
+pub fn get_optional_env_var<T: FromStr>(key: &str) -> Result<Option<T>, CustomError> {
+    match env::var(key) {
+        Ok(value) => match value.trim().parse::<T>() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => {
+                error!("invalid value ({}) for ({}) !", value, key);
+                Err(CustomError::ConfigError(format!("invalid value for ({}) in the env config", key)))
+            }
+        },
+        Err(_) => Ok(None),
     }
-    cols
 }
 
-pub async fn get_count_of_records(total_count_query: String, pool: Pool) -> Result<i64, Error> {
-    let conn = pool.get().await.unwrap();
-    let sql_query = total_count_query.to_string();
-    let stmt = conn.prepare(sql_query.as_str()).await.unwrap();
-    let row = conn.query_one(&stmt, &[]).await.unwrap();
-    Ok(row.get(0))
+fn get_required_env_var(key: &str) -> Result<String, CustomError> {
+    match env::var(key) {
+        Ok(value) => Ok(value),
+        Err(_) => {
+            error!("({}) is not set in the env config !", key);
+            Err(CustomError::ConfigError(format!("({}) is not set in the env config", key)))
+        }
+    }
 }
 
+pub async fn make_db_pool(pool_name: &str) -> Result<Pool, CustomError> {
+    let prefix = get_env_prefix_for_pool(pool_name);
 
-pub async fn get_db_pool_for_table(source_table: &str) -> Result<Pool,CustomError> {
-    println!("source_table : {}", source_table.to_string());
-    return match source_table {
-        "table1" => {
-            Ok(make_db_pool().await)
-        },
-        "table2" => {
-            Ok(make_db_pool().await)
-        },
-        _ => {
-            return Err(CustomError::InvalidTable)
+    let mut cfg = Config::new();
+    cfg.host = Some(get_required_env_var(format!("{}.HOST", prefix).as_str())?);
+    cfg.port = get_optional_env_var::<u16>(format!("{}.PORT", prefix).as_str())?;
+    cfg.user = Some(get_required_env_var(format!("{}.USER", prefix).as_str())?);
+    cfg.password = Some(get_required_env_var(format!("{}.PASSWORD", prefix).as_str())?);
+    cfg.dbname = Some(get_required_env_var(format!("{}.DBNAME", prefix).as_str())?);
+
+    // similarity above which the 'fuzzy' search mode matches (pg_trgm '%' operator) , set on every connection
+    if let Some(threshold) = get_optional_env_var::<f64>("SEARCH.FUZZY_THRESHOLD")? {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(CustomError::ConfigError("SEARCH.FUZZY_THRESHOLD must be between 0 and 1".to_string()))
         }
+        cfg.options = Some(format!("-c pg_trgm.similarity_threshold={}", threshold));
+    }
+
+    // time allowed to open a new connection to postgres
+    let connect_timeout = get_optional_env_var::<u64>(format!("{}.POOL.CONNECT_TIMEOUT_SECS", prefix).as_str())?.map(Duration::from_secs);
+    cfg.connect_timeout = connect_timeout;
+
+    let mut pool_config = PoolConfig::default();
+    if let Some(max_size) = get_optional_env_var::<usize>(format!("{}.POOL.MAX_SIZE", prefix).as_str())? {
+        pool_config.max_size = max_size;
+    }
+    pool_config.timeouts = Timeouts {
+        // time allowed to wait for a free connection when the pool is exhausted
+        wait: get_optional_env_var::<u64>(format!("{}.POOL.WAIT_TIMEOUT_SECS", prefix).as_str())?.map(Duration::from_secs),
+        create: connect_timeout,
+        recycle: None,
     };
-}
+    cfg.pool = Some(pool_config);
 
-pub async fn get_backend_table(table_short_name: &str) -> String {
-    return match table_short_name {
-        "table1" => {
-            "t_random".to_string()
+    match cfg.create_pool(Some(Runtime::Tokio1), tokio_postgres::NoTls) {
+        Ok(pool) => {
+            info!("db pool ({}) created : host ({}) , dbname ({}) , max_size ({})", pool_name, cfg.host.unwrap_or_default(), cfg.dbname.unwrap_or_default(), pool.status().max_size);
+            Ok(pool)
         },
-        "table2" => {
-            "t_data".to_string()
+        Err(e) => {
+            error!("could not create db pool ({}) : {}", pool_name, e);
+            Err(CustomError::ConfigError(format!("could not create db pool ({})", pool_name)))
         }
-        _ => {
-            // default
-            "t_random".to_string()
+    }
+}
+
+// one pool per logical database referenced by the table registry, created once at startup
+pub async fn make_db_pools(registry: &TableRegistry) -> Result<DbPools, CustomError> {
+    let mut pools = HashMap::new();
+    for table in &registry.tables {
+        if !pools.contains_key(&table.pool) {
+            let pool = make_db_pool(table.pool.as_str()).await?;
+            pools.insert(table.pool.to_string(), pool);
+        }
+    }
+    Ok(DbPools { pools })
+}
+
+pub async fn get_count_of_records(total_count_query: &SqlQuery, pool: &Pool) -> Result<i64, CustomError> {
+    let conn = match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not get a db connection : {}", e);
+            return Err(CustomError::DatabaseError("database is unavailable, please try again".to_string()))
         }
     };
+    match conn.query_one(total_count_query.sql.as_str(), &total_count_query.sql_params()).await {
+        Ok(row) => Ok(row.get(0)),
+        Err(e) => {
+            error!("count query failed : {}", e);
+            Err(CustomError::DatabaseError("could not count the rows of the table".to_string()))
+        }
+    }
 }
 
-async fn get_cols_for_table(table_short_name: &str) -> Vec<String> {
-    let mut cols_vec = vec![];
-    return match table_short_name {
-        "table1" => {
-            cols_vec.push("random_num".to_string());
-            cols_vec.push("random_float".to_string());
-            cols_vec.push("md5".to_string());
-            cols_vec
-        },
-        "table2" => {
-            cols_vec.push("my_date".to_string());
-            cols_vec.push("my_data".to_string());
-            cols_vec
+// planner estimate of the number of rows (pg_class.reltuples), None when the table was never analyzed
+pub async fn get_estimated_count_of_records(table: &TableConfig, pool: &Pool) -> Result<Option<i64>, CustomError> {
+    let conn = match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not get a db connection : {}", e);
+            return Err(CustomError::DatabaseError("database is unavailable, please try again".to_string()))
+        }
+    };
+    let backend_table = get_backend_table(table).await;
+    let sql_query = "SELECT reltuples::bigint FROM pg_class WHERE oid = to_regclass($1)";
+    match conn.query_opt(sql_query, &[&backend_table]).await {
+        Ok(Some(row)) => {
+            let estimate: i64 = row.get(0);
+            if estimate < 0 { Ok(None) } else { Ok(Some(estimate)) }
         },
-        _ => {
-            cols_vec.push("random_num".to_string());
-            cols_vec.push("random_float".to_string());
-            cols_vec.push("md5".to_string());
-            cols_vec
+        Ok(None) => Ok(None),
+        Err(e) => {
+            error!("estimate query failed : {}", e);
+            Err(CustomError::DatabaseError("could not estimate the rows of the table".to_string()))
+        }
+    }
+}
+
+// total number of rows of the table (without any search) >> the 'recordsTotal' of DataTables
+// for tables with a 'count_estimate_threshold' the (much cheaper) planner estimate is used
+// as soon as it is above the threshold
+pub async fn get_total_count_of_records(table: &TableConfig, pool: &Pool) -> Result<i64, CustomError> {
+    // the estimate is for the whole table , not for the rows of a row filter
+    if let (Some(threshold), None) = (table.count_estimate_threshold, &table.row_filter) {
+        if let Some(estimate) = get_estimated_count_of_records(table, pool).await? {
+            if estimate >= threshold {
+                debug!("records_total of ({}) is estimated : {}", table.short_name, estimate);
+                return Ok(estimate)
+            }
+        }
+    }
+    let total_count_query = build_count_query(table, &SqlQuery::new()).await;
+    get_count_of_records(&total_count_query, pool).await
+}
+
+pub async fn get_db_pool_for_table(pools: &DbPools, table: &TableConfig) -> Result<Pool, CustomError> {
+    debug!("source_table : {} , pool : {}", table.short_name, table.pool);
+    match pools.pools.get(&table.pool) {
+        Some(pool) => Ok(pool.clone()),
+        None => {
+            error!("no db pool ({}) for table ({}) !", table.pool, table.short_name);
+            Err(CustomError::ConfigError(format!("no database configured for table ({})", table.short_name)))
         }
     }
 }
 
-pub async fn get_table_column_mapping(table_short_name: &str) -> HashMap<String, String> {
+// fully qualified (and quoted) backend table >> example : "public"."t_random"
+pub async fn get_backend_table(table: &TableConfig) -> String {
+    format!("{}.{}", quote_identifier(table.schema.as_str()), quote_identifier(table.backend_table.as_str()))
+}
+
+pub async fn get_table_column_mapping(table: &TableConfig) -> HashMap<String, String> {
     let mut map: HashMap<String, String> = HashMap::new();
-    let cols_vec = get_cols_for_table(table_short_name).await;
-    for (index, value) in cols_vec.iter().enumerate() {
+    for (index, value) in table.columns.iter().enumerate() {
         map.insert(index.to_string(), value.to_string());
     }
     map
 }
 
-pub async fn get_backend_table_columns(table_short_name: &str) -> Vec<String> {
-    let mut cols = vec![];
-    let mapping = get_table_column_mapping(table_short_name).await;
-    for(_, column_name) in &mapping {
-        cols.push(column_name.to_string());
-    }
-    cols
+// select list of a table >> columns of a type without a native JSON conversion are read as text
+// example : "random_num","md5","created_at","ip_address"::text AS "ip_address"
+// masked columns (see access_ops) are read as a constant >> example : '****'::text AS "md5"
+// 'columns' : projection (JSON API) in the requested order , every column of the table when empty
+pub async fn get_select_columns(table: &TableConfig, columns: &[String]) -> Vec<String> {
+    let column_info: Vec<&ColumnInfo> = if columns.is_empty() {
+        table.column_info.iter().collect()
+    } else {
+        columns.iter().filter_map(|name| table.column_info.iter().find(|c| &c.name == name)).collect()
+    };
+    column_info.iter().map(|c| {
+        let column = quote_identifier(c.name.as_str());
+        if c.masked {
+            format!("'{}'::text AS {}", MASKED_VALUE, column)
+        } else if is_native_type(c.udt_name.as_str()) {
+            column
+        } else {
+            format!("{}::text AS {}", column, column)
+        }
+    }).collect()
 }
 
-pub async fn export_table_to_csv(pool: Pool, table_name: &str, table_columns: Vec<String>, search_strings: Vec<String>, pattern_match: String, search_type: String) -> Result<ExportResults, Error> {
+// rows are streamed from postgres (query_raw) and handed to the writer of the format as they arrive,
+// the result set is never held in memory
+// 'progress' is updated while writing and can cancel the export (see export_ops)
+// created_by , request_id : recorded in the metadata sidecar of the export
+pub async fn export_table_to_file(pool: Pool, table: &TableConfig, where_clause: &SqlQuery, format: ExportFormat, progress: &ExportProgress, created_by: &str, request_id: &str) -> Result<ExportResults, CustomError> {
     let start = Instant::now();
     // Get a connection from the pool
-    let mut client = pool.get().await.unwrap();
-
-    let mut main_query = "".to_string();
-
-    let backend_table = get_backend_table(table_name).await;
-    println!("backend_table : {}", backend_table);
-
-    let table_columns_separated_by_commas = table_columns.join(",");
+    let client = match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not get a db connection : {}", e);
+            return Err(CustomError::DatabaseError("database is unavailable, please try again".to_string()))
+        }
+    };
 
-    // '___' is sent from the UI : which tells the backend to export all the rows of the table
-    if search_strings.len() == 1 && search_strings.get(0).unwrap().to_string() == "___".to_string() {
-        main_query = format!("SELECT {} FROM {}", table_columns_separated_by_commas, backend_table);
-    } else {
-        let inner_query = get_inner_query(table_columns, search_strings, pattern_match, search_type).await.unwrap();
-        main_query = format!("SELECT {} FROM {} WHERE {}", table_columns_separated_by_commas, backend_table, inner_query);
+    if let Ok(mut cancel_token) = progress.cancel_token.lock() {
+        *cancel_token = Some(client.cancel_token());
     }
 
-    println!("main_query : |{}|", main_query);
-
-    // Prepare your SQL query
-    let stmt = client.prepare(main_query.as_str()).await.unwrap();
-
-    // Execute the query
-    let rows = client.query(&stmt, &[]).await.unwrap();
-
-    let file_name = format!("{}.csv", Uuid::new_v4());
-    let dir_name = "data_dir".to_string();
-    let complete_file_path = format!("{}/{}", dir_name, file_name);
+    let main_query = build_export_query(table, where_clause).await;
 
-    // Create a writer to write to a CSV file
-    let file = File::create(complete_file_path.clone()).expect("Unable to create file");
-    let mut wtr = Writer::from_writer(file);
+    debug!(sql = %main_query.sql, params = ?get_redacted_params(&main_query.params), "export query");
 
-    // write headers first
-    let columns = stmt.columns();
-    let headers: Vec<&str> = columns.iter().map(|col| col.name()).collect();
-    wtr.write_record(&headers).unwrap();
+    // Prepare your SQL query
+    let stmt = match client.prepare(main_query.sql.as_str()).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("could not prepare export query : {}", e);
+            return Err(CustomError::DatabaseError("could not query the table".to_string()))
+        }
+    };
 
-    ///////////////////
+    let file_name = format!("{}.{}", Uuid::new_v4(), format.extension());
+    let complete_file_path = format!("{}/{}", EXPORT_DIR, file_name);
+    // the export only gets its final name once complete , so it is never listed (or downloaded) half written
+    let partial_file_path = format!("{}{}", complete_file_path, PARTIAL_EXPORT_SUFFIX);
 
-    let mut data_rows_1:Vec<Data1> = Vec::new();
-    let mut data_rows_2:Vec<Data2> = Vec::new();
+    let mut writer = make_export_writer(format, partial_file_path.as_str()).await?;
 
-    match table_name {
-        "table1" => {
-            data_rows_1 = fetch(&client, main_query.as_str()).await.unwrap();
-            write_to_csv(data_rows_1, complete_file_path.as_str(), wtr).await.unwrap();
-        },
-        "table2" => {
-            data_rows_2 = fetch(&client, main_query.as_str()).await.unwrap();
-            write_to_csv(data_rows_2, complete_file_path.as_str(), wtr).await.unwrap();
-        },
-        _ => {
-            // default is 'table1'
-            data_rows_1 = fetch(&client, main_query.as_str()).await.unwrap();
-            write_to_csv(data_rows_1, complete_file_path.as_str(), wtr).await.unwrap();
-        },
+    let rows = match client.query_raw(&stmt, main_query.sql_params()).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("export query failed : {}", e);
+            remove_partial_export(partial_file_path.as_str()).await;
+            return Err(CustomError::DatabaseError("could not query the table".to_string()))
+        }
     };
 
-    ///////////////////
+    let total_rows = match write_rows(writer.as_mut(), stmt.columns(), rows, progress).await {
+        Ok(n) => n,
+        Err(e) => {
+            remove_partial_export(partial_file_path.as_str()).await;
+            return Err(e)
+        }
+    };
 
-    // write_csv_data(table_name, &rows, wtr).await;
+    let size_bytes = tokio::fs::metadata(partial_file_path.as_str()).await.map(|m| m.len()).unwrap_or_default();
+
+    // the sidecar exists before the rename , so a listed export always has its table and owner
+    let metadata = ExportMetadata {
+        file_name: file_name.to_string(),
+        table_name: table.short_name.to_string(),
+        format: Some(format),
+        rows: Some(total_rows),
+        size_bytes,
+        created_by: created_by.to_string(),
+        request_id: request_id.to_string(),
+        created_at: Utc::now(),
+    };
+    if let Err(e) = write_export_metadata(&metadata).await {
+        remove_partial_export(partial_file_path.as_str()).await;
+        return Err(e)
+    }
 
-    println!("CSV File Written : {}", complete_file_path.clone().to_string());
+    if let Err(e) = tokio::fs::rename(partial_file_path.as_str(), complete_file_path.as_str()).await {
+        error!("could not rename ({}) : {}", partial_file_path, e);
+        remove_partial_export(partial_file_path.as_str()).await;
+        remove_export_metadata(file_name.as_str()).await;
+        return Err(CustomError::ExportError("could not write the export file".to_string()))
+    }
 
-    let total_rows = rows.len() as i32;
+    info!("{:?} File Written : {} , rows : {}", format, complete_file_path, total_rows);
 
     let duration = start.elapsed().as_secs_f64();
 
-    let csv_export_results = ExportResults {
-        csv_file_path: complete_file_path,
+    let export_results = ExportResults {
+        file_name,
+        file_path: complete_file_path.to_string(),
+        content_type: format.content_type().to_string(),
+        size_bytes,
         rows: total_rows,
         time_taken_for_export: duration,
     };
 
-    Ok(csv_export_results)
+    Ok(export_results)
 }
 
-/* ************************************************************************************* */
+// returns the number of rows written (the header is not counted)
+async fn write_rows(writer: &mut dyn ExportWriter, columns: &[Column], rows: RowStream, progress: &ExportProgress) -> Result<i64, CustomError> {
+    writer.write_header(columns).await?;
 
-#[async_trait]
-pub trait FromRow: Sized {
-    async fn from_row(row: &tokio_postgres::Row) -> Result<Self, tokio_postgres::Error>
-    where Self: Sized;
-}
+    let mut total_rows: i64 = 0;
 
-#[async_trait]
-impl FromRow for Data1 {
-    async fn from_row(row: &tokio_postgres::Row) -> Result<Self, tokio_postgres::Error> {
-        let random_num = row.try_get("random_num").unwrap_or_else(|_| 0);
-        let random_float = row.try_get("random_float").unwrap_or_else(|_| 0.0);
-        let md5 = row.try_get("md5").unwrap_or_else(|_| "missing_md5".to_string());
-
-        // let random_num = row.get("random_num");
-        // let random_float = row.get("random_float");
-        // let md5 = row.get("md5");
-
-        Ok(Data1{
-            random_num,
-            random_float,
-            md5,
-        })
-    }
-}
-
-#[async_trait]
-impl FromRow for Data2 {
-    async fn from_row(row: &tokio_postgres::Row) -> Result<Self, tokio_postgres::Error> {
-        let my_date = row.try_get("my_date").unwrap_or_else(|_| "missing_my_date".to_string());
-        let my_data = row.try_get("my_data").unwrap_or_else(|_| "missing_my_data".to_string());
-
-        Ok(Data2{
-            my_date,
-            my_data,
-        })
-    }
-}
-
-/*
-Note the use of + Send in the generic type constraint.
-This is required because the async_trait macro transforms async trait methods into methods that return Send futures.
-*/
-
-pub async fn fetch<T: FromRow + Send>(client: &deadpool_postgres::Client, query: &str) -> Result<Vec<T>, tokio_postgres::Error> {
-    let rows = client.query(query, &[]).await?;
-    let mut results = Vec::new();
-
-    for row in rows {
-        results.push(T::from_row(&row).await?);
+    pin_mut!(rows);
+    while let Some(row) = rows.next().await {
+        if progress.cancelled.load(Ordering::Relaxed) {
+            return Err(CustomError::ExportError("export was cancelled".to_string()))
+        }
+        let row = match row {
+            Ok(r) => r,
+            Err(e) => {
+                error!("export query failed after ({}) rows : {}", total_rows, e);
+                return Err(CustomError::DatabaseError("could not read all the rows of the table".to_string()))
+            }
+        };
+        writer.write_row(&row).await?;
+        total_rows += 1;
+        progress.rows.store(total_rows, Ordering::Relaxed);
     }
 
-    Ok(results)
-}
-
-/* ---- csv ---- */
+    writer.finish().await?;
 
-#[async_trait]
-pub trait ToCsvRow {
-    async fn to_csv_row(&self) -> Vec<String>;
+    Ok(total_rows)
 }
 
-#[async_trait]
-impl ToCsvRow for Data1 {
-    async fn to_csv_row(&self) -> Vec<String> {
-        vec![
-            self.random_num.to_string(),
-            self.random_float.to_string(),
-            self.md5.to_string(),
-        ]
+// a failed export does not leave a truncated file behind
+async fn remove_partial_export(file_path: &str) {
+    match tokio::fs::remove_file(file_path).await {
+        Ok(_) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => error!("could not remove partial export ({}) : {}", file_path, e),
     }
 }
 
-#[async_trait]
-impl ToCsvRow for Data2 {
-    async fn to_csv_row(&self) -> Vec<String> {
-        vec![
-            self.my_date.to_string(),
-            self.my_data.to_string(),
-        ]
-    }
-}
-
-async fn write_to_csv<T: FromRow + Send + ToCsvRow>(items: Vec<T>, file_path: &str, mut wtr: Writer<File>) -> Result<(), Box<dyn std::error::Error>> {
-    // let mut wtr = Writer::from_path(file_path).unwrap();
-    for item in items {
-        wtr.write_record(item.to_csv_row().await).unwrap();
-    }
-    wtr.flush().unwrap();
-    Ok(())
-}
-
 /* ************************************************************************************* */
 
-pub async fn get_inner_query(table_columns: Vec<String>, search_strings: Vec<String>, pattern_match: String, search_type: String) -> Result<String, CustomError> {
-    let mut inner_query = "".to_string();
-
-    if !(search_type == "and" || search_type == "or") {
-        println!("error : search_type is neither 'and' nor 'or' !");
-        return Err(CustomError::QueryError)
-    }
-
-    if !(pattern_match == "like" || pattern_match == "exact") {
-        println!("error : pattern_match is neither 'like' nor 'exact' !");
-        return Err(CustomError::QueryError)
-    }
-
-    if table_columns.len() == 0 {
-        println!("error : table_columns length is ZERO !");
-        return Err(CustomError::QueryError)
-    }
-
-    if search_strings.len() == 0 {
-        println!("error : search_strings length is ZERO !");
-        return Err(CustomError::QueryError)
-    }
-
-    if pattern_match.as_str() == "exact" { // exact string match
-        // search all JSON fields for possible match
-        // this can also be applied if there are other columns
-        if search_type == "and" {
-            inner_query = inner_query + " ( ";
-            let mut search_string_counter = 1;
-            for my_search_str in search_strings.to_owned() {
-                inner_query = inner_query + " ( ";
-                let mut column_counter = 1;
-                // --------------------------------------
-                for my_column in table_columns.to_owned() {
-                    if column_counter == table_columns.len() as i32 {
-                        inner_query = inner_query + format!(" lower({}::text) = lower('{}') ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    } else {
-                        inner_query = inner_query + format!(" lower({}::text) = lower('{}') OR ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    }
-                    column_counter += 1;
-                }
-                if search_string_counter == search_strings.len() as i32 {
-                    inner_query = inner_query + " ) ";
-                } else {
-                    inner_query = inner_query + " ) AND ";
-                }
-                search_string_counter += 1;
-                // --------------------------------------
-            }
-            inner_query = inner_query + " ) ";
-        } else if search_type == "or" {
-            inner_query = inner_query + " ( ";
-            let mut search_string_counter = 1;
-            for my_search_str in search_strings.to_owned() {
-                inner_query = inner_query + " ( ";
-                let mut column_counter = 1;
-                // --------------------------------------
-                for my_column in table_columns.to_owned() {
-                    if column_counter == table_columns.len() as i32 {
-                        inner_query = inner_query + format!(" lower({}::text) = lower('{}') ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    } else {
-                        inner_query = inner_query + format!(" lower({}::text) = lower('{}') OR ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    }
-                    column_counter += 1;
-                }
-                if search_string_counter == search_strings.len() as i32 {
-                    inner_query = inner_query + " ) ";
-                } else {
-                    inner_query = inner_query + " ) OR ";
-                }
-                search_string_counter += 1;
-                // --------------------------------------
-            }
-            inner_query = inner_query + " ) ";
-        } else {
-            return Err(CustomError::QueryError)
+pub async fn fetch_as_json(pool: &Pool, query: &SqlQuery) -> Result<Vec<serde_json::Value>, CustomError> {
+    let client = match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not get a db connection : {}", e);
+            return Err(CustomError::DatabaseError("database is unavailable, please try again".to_string()))
         }
-
-    } else if pattern_match.as_str() == "like" { // pattern match
-        // search all JSON fields for possible match
-        // this can also be applied if there are other columns
-        if search_type == "and" {
-            inner_query = inner_query + " ( ";
-            let mut search_string_counter = 1;
-            for my_search_str in search_strings.to_owned() {
-                inner_query = inner_query + " ( ";
-                let mut column_counter = 1;
-                // --------------------------------------
-                for my_column in table_columns.to_owned() {
-                    if column_counter == table_columns.len() as i32 {
-                        inner_query = inner_query + format!(" lower({}::text) like lower('%{}%') ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    } else {
-                        inner_query = inner_query + format!(" lower({}::text) like lower('%{}%') OR ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    }
-                    column_counter += 1;
-                }
-                if search_string_counter == search_strings.len() as i32 {
-                    inner_query = inner_query + " ) ";
-                } else {
-                    inner_query = inner_query + " ) AND ";
-                }
-                search_string_counter += 1;
-                // --------------------------------------
-            }
-            inner_query = inner_query + " ) ";
-        } else if search_type == "or" {
-            inner_query = inner_query + " ( ";
-            let mut search_string_counter = 1;
-            for my_search_str in search_strings.to_owned() {
-                inner_query = inner_query + " ( ";
-                let mut column_counter = 1;
-                // --------------------------------------
-                for my_column in table_columns.to_owned() {
-                    if column_counter == table_columns.len() as i32 {
-                        inner_query = inner_query + format!(" lower({}::text) like lower('%{}%') ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    } else {
-                        inner_query = inner_query + format!(" lower({}::text) like lower('%{}%') OR ", my_column.to_string(), my_search_str.to_lowercase()).as_str();
-                    }
-                    column_counter += 1;
-                }
-                if search_string_counter == search_strings.len() as i32 {
-                    inner_query = inner_query + " ) ";
-                } else {
-                    inner_query = inner_query + " ) OR ";
-                }
-                search_string_counter += 1;
-                // --------------------------------------
-            }
-            inner_query = inner_query + " ) ";
-        } else {
-            return Err(CustomError::QueryError)
+    };
+    let rows = match client.query(query.sql.as_str(), &query.sql_params()).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("query failed : {}", e);
+            return Err(CustomError::DatabaseError("could not query the table".to_string()))
         }
-    } else {
-        return Err(CustomError::QueryError)
-    }
-    Ok(inner_query)
+    };
+    Ok(rows.iter().map(row_to_json_object).collect())
 }
-
-/* ************************************************************************************* */
\ No newline at end of file
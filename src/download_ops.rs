@@ -0,0 +1,198 @@
+use std::time::Duration;
+use actix_files::NamedFile;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType, HeaderValue};
+use actix_web::mime::Mime;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+use tracing::{error, info, warn};
+use crate::auth_ops::get_current_user;
+use crate::data_types::{CustomError, DownloadConfig, DownloadParams, ExportFormat, ExportMetadata};
+use crate::db_ops::get_optional_env_var;
+use crate::retention_ops::{get_export_metadata, get_export_path};
+use crate::request_ops::get_client_ip;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// exports are only served through signed links : downloads/<file_name>?expires=<unix time>&sig=<hex>
+// the signature covers the file , the user who asked for the link and the expiry
+pub fn load_download_config() -> Result<DownloadConfig, CustomError> {
+    let link_ttl_secs = get_optional_env_var::<u64>("DOWNLOAD.LINK_TTL_SECS")?.unwrap_or(15 * 60);
+
+    let secret = match get_optional_env_var::<String>("DOWNLOAD.SECRET")? {
+        Some(s) if s.len() >= 32 => s.into_bytes(),
+        Some(_) => return Err(CustomError::ConfigError("DOWNLOAD.SECRET must be at least 32 characters".to_string())),
+        None => {
+            warn!("DOWNLOAD.SECRET is not set , using a random secret (download links will not survive a restart)");
+            format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple()).into_bytes()
+        }
+    };
+
+    info!("download links : link_ttl ({}s)", link_ttl_secs);
+
+    Ok(DownloadConfig {
+        secret,
+        link_ttl: Duration::from_secs(link_ttl_secs),
+    })
+}
+
+// who is asking : the logged in user , or the client address when authentication is disabled
+// (the same value is recorded as 'created_by' of an export)
+pub fn get_client_identity(req: &HttpRequest) -> String {
+    match get_current_user(req) {
+        Some(user) => user.username,
+        None => get_client_ip(req),
+    }
+}
+
+/* ************************************************************************************* */
+
+fn get_signer(config: &DownloadConfig, file_name: &str, user: &str, expires: i64) -> Result<HmacSha256, CustomError> {
+    let mut mac = match HmacSha256::new_from_slice(&config.secret) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("invalid download secret : {}", e);
+            return Err(CustomError::ConfigError("download links are unavailable".to_string()))
+        }
+    };
+    mac.update(format!("{}|{}|{}", file_name, user, expires).as_bytes());
+    Ok(mac)
+}
+
+// relative link , the UI prefixes it with its origin
+pub fn make_download_link(config: &DownloadConfig, file_name: &str, user: &str) -> Result<String, CustomError> {
+    let expires = Utc::now().timestamp() + config.link_ttl.as_secs() as i64;
+    let sig = hex::encode(get_signer(config, file_name, user, expires)?.finalize().into_bytes());
+    Ok(format!("downloads/{}?expires={}&sig={}", file_name, expires, sig))
+}
+
+pub fn verify_download_link(config: &DownloadConfig, file_name: &str, user: &str, params: &DownloadParams) -> Result<(), CustomError> {
+    if params.expires < Utc::now().timestamp() {
+        return Err(CustomError::Forbidden("download link has expired, please export again".to_string()))
+    }
+    let sig = match hex::decode(params.sig.as_str()) {
+        Ok(s) => s,
+        Err(_) => return Err(CustomError::Forbidden("invalid download link".to_string()))
+    };
+    // constant time comparison
+    if get_signer(config, file_name, user, params.expires)?.verify_slice(&sig).is_err() {
+        warn!("invalid download signature : file ({}) , user ({})", file_name, user);
+        return Err(CustomError::Forbidden("invalid download link".to_string()))
+    }
+    Ok(())
+}
+
+/* ************************************************************************************* */
+
+// <table>_<YYYYmmdd_HHMMSS>.<ext> , e.g. table1_20240101_120000.csv
+fn get_download_name(metadata: &ExportMetadata, extension: &str) -> String {
+    let table_name: String = metadata.table_name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}_{}.{}", table_name, metadata.created_at.format("%Y%m%d_%H%M%S"), extension)
+}
+
+pub async fn get_download_response(req: &HttpRequest, config: &DownloadConfig, file_name: &str, params: &DownloadParams) -> Result<HttpResponse, CustomError> {
+    verify_download_link(config, file_name, get_client_identity(req).as_str(), params)?;
+
+    let metadata = get_export_metadata(file_name).await?;
+    let extension = file_name.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+    let content_type = match metadata.format.or_else(|| ExportFormat::from_extension(extension)) {
+        Some(f) => f.content_type(),
+        None => "application/octet-stream",
+    };
+
+    let file = match NamedFile::open_async(get_export_path(file_name)).await {
+        Ok(f) => f,
+        Err(e) => {
+            error!("could not open export ({}) : {}", file_name, e);
+            return Err(CustomError::NotFound(format!("unknown export ({})", file_name)))
+        }
+    };
+
+    let mime = content_type.parse::<Mime>().unwrap_or(actix_web::mime::APPLICATION_OCTET_STREAM);
+    let file = file
+        .set_content_type(mime)
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(get_download_name(&metadata, extension))],
+        });
+
+    info!("export downloaded : {} ({})", file_name, metadata.table_name);
+
+    let mut response = file.into_response(req);
+    // links are per user , shared caches must not keep the file
+    response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+    Ok(response)
+}
+
+/* ************************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_NAME: &str = "4ec4f79e-919b-4a3a-a5ad-a1a799f0dccf.csv";
+
+    fn get_config() -> DownloadConfig {
+        DownloadConfig {
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+            link_ttl: Duration::from_secs(60),
+        }
+    }
+
+    // downloads/<file_name>?expires=<unix time>&sig=<hex>
+    fn get_params(link: &str) -> DownloadParams {
+        let (_, query) = link.split_once('?').unwrap();
+        let (expires, sig) = query.split_once('&').unwrap();
+        DownloadParams {
+            expires: expires.trim_start_matches("expires=").parse().unwrap(),
+            sig: sig.trim_start_matches("sig=").to_string(),
+        }
+    }
+
+    fn is_forbidden(result: Result<(), CustomError>) -> bool {
+        matches!(result, Err(CustomError::Forbidden(_)))
+    }
+
+    #[test]
+    fn signed_link_is_accepted() {
+        let config = get_config();
+        let link = make_download_link(&config, FILE_NAME, "alice").unwrap();
+        assert!(link.starts_with(format!("downloads/{}?", FILE_NAME).as_str()));
+        assert!(verify_download_link(&config, FILE_NAME, "alice", &get_params(link.as_str())).is_ok());
+    }
+
+    #[test]
+    fn expired_link_is_refused() {
+        let config = get_config();
+        let expires = Utc::now().timestamp() - 1;
+        let sig = hex::encode(get_signer(&config, FILE_NAME, "alice", expires).unwrap().finalize().into_bytes());
+        assert!(is_forbidden(verify_download_link(&config, FILE_NAME, "alice", &DownloadParams { expires, sig })));
+    }
+
+    #[test]
+    fn tampered_link_is_refused() {
+        let config = get_config();
+        let params = get_params(make_download_link(&config, FILE_NAME, "alice").unwrap().as_str());
+
+        // another file , another user
+        assert!(is_forbidden(verify_download_link(&config, "19a0ebd6-c666-4e7b-82e6-8c17b61b24f5.csv", "alice", &params)));
+        assert!(is_forbidden(verify_download_link(&config, FILE_NAME, "bob", &params)));
+        // a later expiry , a changed or malformed signature
+        let extended = DownloadParams { expires: params.expires + 3600, sig: params.sig.to_string() };
+        assert!(is_forbidden(verify_download_link(&config, FILE_NAME, "alice", &extended)));
+        let flipped = format!("{}{}", if params.sig.starts_with('0') { '1' } else { '0' }, &params.sig[1..]);
+        assert!(is_forbidden(verify_download_link(&config, FILE_NAME, "alice", &DownloadParams { expires: params.expires, sig: flipped })));
+        assert!(is_forbidden(verify_download_link(&config, FILE_NAME, "alice", &DownloadParams { expires: params.expires, sig: "xyz".to_string() })));
+    }
+
+    #[test]
+    fn link_is_bound_to_the_secret() {
+        let params = get_params(make_download_link(&get_config(), FILE_NAME, "alice").unwrap().as_str());
+        let other = DownloadConfig { secret: b"fedcba9876543210fedcba9876543210".to_vec(), link_ttl: Duration::from_secs(60) };
+        assert!(is_forbidden(verify_download_link(&other, FILE_NAME, "alice", &params)));
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, MutexGuard};
+use std::time::Instant;
+use actix_web::web;
+use deadpool_postgres::Pool;
+use uuid::Uuid;
+use tracing::{error, info, info_span, warn, Instrument};
+use crate::audit_ops::finish_audit_event;
+use crate::data_types::{AuditEvent, CustomError, DownloadConfig, ExportFormat, ExportJob, ExportJobs, ExportProgress, ExportResults, ExportState, JsonResponseWithCSVExportData, TableConfig};
+use crate::db_ops::export_table_to_file;
+use crate::download_ops::make_download_link;
+use crate::metrics_ops::observe_export;
+use crate::query_ops::SqlQuery;
+use crate::request_ops::get_request_id;
+
+// exports run as background tasks , the HTTP request only starts the job
+// the UI polls GET /exports/{id} until the job is no longer 'running'
+// the audit entry of the export is written when the job ends , its user is the 'created_by' of the export
+pub fn start_export_job(jobs: web::Data<ExportJobs>, pool: Pool, table: TableConfig, where_clause: SqlQuery, format: ExportFormat, download: &DownloadConfig, mut event: AuditEvent) -> Result<JsonResponseWithCSVExportData, CustomError> {
+    let job_id = Uuid::new_v4().to_string();
+    let progress = Arc::new(ExportProgress::default());
+    let created_by = event.entry.username.to_string();
+
+    let job = ExportJob {
+        table_name: table.short_name.to_string(),
+        format,
+        created_by: created_by.to_string(),
+        state: ExportState::Running,
+        started_at: Instant::now(),
+        finished_at: None,
+        progress: progress.clone(),
+        results: None,
+        error: None,
+    };
+
+    let registered = get_export_job_status(job_id.as_str(), &job, download, created_by.as_str())
+        .and_then(|status| {
+            lock_export_jobs(&jobs)?.insert(job_id.to_string(), job);
+            Ok(status)
+        });
+    let status = match registered {
+        Ok(s) => s,
+        Err(e) => {
+            finish_audit_event(event, None, e.code());
+            return Err(e)
+        }
+    };
+
+    info!("export job ({}) started : table ({}) , format ({:?})", job_id, table.short_name, format);
+
+    // the job outlives the request , it logs in its own span
+    let request_id = get_request_id();
+    let span = info_span!("export", job_id = %job_id, request_id = %request_id, table = %table.short_name);
+    let task_job_id = job_id.to_string();
+    tokio::spawn(async move {
+        let started_at = Instant::now();
+        let result = export_table_to_file(pool, &table, &where_clause, format, &progress, created_by.as_str(), request_id.as_str()).await;
+        let (rows, size_bytes, status) = match &result {
+            Ok(r) => {
+                event.entry.export_file = Some(r.file_name.to_string());
+                (r.rows, r.size_bytes, "ok")
+            },
+            Err(_) if progress.cancelled.load(Ordering::Relaxed) => (progress.rows.load(Ordering::Relaxed), 0, "cancelled"),
+            Err(e) => (progress.rows.load(Ordering::Relaxed), 0, e.code()),
+        };
+        observe_export(table.short_name.as_str(), format.extension(), status, rows, size_bytes, started_at.elapsed());
+        finish_audit_event(event, Some(rows), status);
+        finish_export_job(&jobs, task_job_id.as_str(), result);
+    }.instrument(span));
+
+    Ok(status)
+}
+
+fn finish_export_job(jobs: &ExportJobs, job_id: &str, result: Result<ExportResults, CustomError>) {
+    let mut jobs = match jobs.jobs.lock() {
+        Ok(j) => j,
+        Err(e) => {
+            error!("export jobs lock is poisoned : {}", e);
+            return
+        }
+    };
+    let job = match jobs.get_mut(job_id) {
+        Some(j) => j,
+        None => return
+    };
+
+    job.finished_at = Some(Instant::now());
+    match result {
+        Ok(results) => {
+            info!("export job ({}) completed : {} rows", job_id, results.rows);
+            job.state = ExportState::Completed;
+            job.results = Some(results);
+        },
+        Err(e) if job.progress.cancelled.load(Ordering::Relaxed) => {
+            info!("export job ({}) cancelled : {}", job_id, e);
+            job.state = ExportState::Cancelled;
+            job.error = Some("export was cancelled".to_string());
+        },
+        Err(e) => {
+            error!("export job ({}) failed : {}", job_id, e);
+            job.state = ExportState::Failed;
+            job.error = Some(e.to_string());
+        },
+    }
+}
+
+/* ************************************************************************************* */
+
+// a job is only visible to the user who started it , other users get the same answer as for an unknown job
+fn get_owned_export_job<'a>(jobs: &'a HashMap<String, ExportJob>, job_id: &str, user: &str) -> Result<&'a ExportJob, CustomError> {
+    match jobs.get(job_id) {
+        Some(job) if job.created_by == user => Ok(job),
+        Some(_) => {
+            warn!("({}) asked for export job ({}) of another user", user, job_id);
+            Err(CustomError::NotFound(format!("unknown export job ({})", job_id)))
+        },
+        None => Err(CustomError::NotFound(format!("unknown export job ({})", job_id)))
+    }
+}
+
+// the download link of a completed export is signed for the user who started it (the caller)
+pub fn get_export_job(jobs: &ExportJobs, job_id: &str, download: &DownloadConfig, user: &str) -> Result<JsonResponseWithCSVExportData, CustomError> {
+    let jobs = lock_export_jobs(jobs)?;
+    let job = get_owned_export_job(&jobs, job_id, user)?;
+    get_export_job_status(job_id, job, download, user)
+}
+
+// only the user who started the export may cancel it (admins purge exports through /admin/exports)
+// the query running on postgres is cancelled , the export task then removes the partial file
+pub async fn cancel_export_job(jobs: &ExportJobs, job_id: &str, download: &DownloadConfig, user: &str) -> Result<JsonResponseWithCSVExportData, CustomError> {
+    let cancel_token = {
+        let jobs = lock_export_jobs(jobs)?;
+        let job = get_owned_export_job(&jobs, job_id, user)?;
+        if job.state != ExportState::Running {
+            return Err(CustomError::ValidationError(format!("export job ({}) is not running", job_id)))
+        }
+        job.progress.cancelled.store(true, Ordering::Relaxed);
+        let cancel_token = match job.progress.cancel_token.lock() {
+            Ok(t) => t.clone(),
+            Err(_) => None
+        };
+        cancel_token
+    };
+
+    if let Some(token) = cancel_token {
+        if let Err(e) = token.cancel_query(tokio_postgres::NoTls).await {
+            error!("could not cancel the query of export job ({}) : {}", job_id, e);
+        }
+    }
+
+    info!("export job ({}) cancellation requested", job_id);
+
+    get_export_job(jobs, job_id, download, user)
+}
+
+/* ************************************************************************************* */
+
+fn lock_export_jobs(jobs: &ExportJobs) -> Result<MutexGuard<'_, HashMap<String, ExportJob>>, CustomError> {
+    match jobs.jobs.lock() {
+        Ok(j) => Ok(j),
+        Err(e) => {
+            error!("export jobs lock is poisoned : {}", e);
+            Err(CustomError::ExportError("export jobs are unavailable".to_string()))
+        }
+    }
+}
+
+// status : 202 while running , 200 once completed (message is the download link) , 500 failed , 410 cancelled
+fn get_export_job_status(job_id: &str, job: &ExportJob, download: &DownloadConfig, user: &str) -> Result<JsonResponseWithCSVExportData, CustomError> {
+    let elapsed = match job.finished_at {
+        Some(f) => f.duration_since(job.started_at).as_secs_f64(),
+        None => job.started_at.elapsed().as_secs_f64(),
+    };
+
+    let (message, status, rows, time_taken_for_export) = match job.state {
+        ExportState::Running => ("export is running".to_string(), 202, job.progress.rows.load(Ordering::Relaxed), elapsed),
+        ExportState::Completed => match &job.results {
+            Some(r) => (make_download_link(download, r.file_name.as_str(), user)?, 200, r.rows, r.time_taken_for_export),
+            None => ("".to_string(), 200, 0, elapsed),
+        },
+        ExportState::Failed => (job.error.clone().unwrap_or_default(), 500, job.progress.rows.load(Ordering::Relaxed), elapsed),
+        ExportState::Cancelled => (job.error.clone().unwrap_or_default(), 410, job.progress.rows.load(Ordering::Relaxed), elapsed),
+    };
+
+    Ok(JsonResponseWithCSVExportData {
+        job_id: job_id.to_string(),
+        table_name: job.table_name.to_string(),
+        format: job.format,
+        state: job.state,
+        message,
+        status,
+        rows,
+        time_taken_for_export,
+    })
+}
@@ -0,0 +1,92 @@
+use crate::data_types::{FullTextConfig, SearchFilters, TableConfig};
+use crate::query_ops::SqlQuery;
+use crate::search_ops::get_positive_terms;
+use crate::string_ops::quote_identifier;
+
+// 'fulltext' search mode : the searched columns are concatenated into one document
+//
+//   to_tsvector('english'::regconfig, coalesce("a"::text, '') || ' ' || coalesce("b"::text, ''))
+//       @@ websearch_to_tsquery('english'::regconfig, $1)
+//
+// the language is written as a literal , so a GIN index on the same expression is used by postgres
+
+const TEXT_TYPES: [&str; 4] = ["text", "varchar", "bpchar", "name"];
+
+// the 'fulltext' section of the table , or 'simple' over the text columns of the table
+// masked columns (see access_ops) are left out of the document and its headline
+pub fn get_fulltext_config(table: &TableConfig) -> FullTextConfig {
+    let mut config = table.fulltext.clone().unwrap_or_default();
+    if config.columns.is_empty() {
+        config.columns = table.column_info.iter()
+            .filter(|c| TEXT_TYPES.contains(&c.udt_name.as_str()))
+            .map(|c| c.name.to_string())
+            .collect();
+    }
+    config.columns.retain(|c| !table.column_info.iter().any(|i| &i.name == c && i.masked));
+    config
+}
+
+// the fulltext columns the search is allowed to look into , in the order of the config
+pub fn get_document_columns(fulltext: &FullTextConfig, searchable_columns: &[String]) -> Vec<String> {
+    fulltext.columns.iter().filter(|c| searchable_columns.contains(c)).cloned().collect()
+}
+
+fn get_document_text(columns: &[String]) -> String {
+    columns.iter()
+        .map(|c| format!("coalesce({}::text, '')", quote_identifier(c)))
+        .collect::<Vec<String>>()
+        .join(" || ' ' || ")
+}
+
+fn get_document(language: &str, columns: &[String]) -> String {
+    format!("to_tsvector('{}'::regconfig, {})", language, get_document_text(columns))
+}
+
+// terms with spaces (quoted phrases in the search box) are searched as phrases
+fn get_tsquery(query: &mut SqlQuery, language: &str, value: &str) -> String {
+    let value = if value.chars().any(char::is_whitespace) {
+        format!("\"{}\"", value.replace('"', " "))
+    } else {
+        value.to_string()
+    };
+    format!("websearch_to_tsquery('{}'::regconfig, {})", language, query.bind(value))
+}
+
+pub fn get_fulltext_condition(query: &mut SqlQuery, fulltext: &FullTextConfig, columns: &[String], value: &str) -> String {
+    // none of the searched columns is part of the document >> nothing can match
+    if columns.is_empty() {
+        return " FALSE ".to_string()
+    }
+    format!(" {} @@ {} ", get_document(fulltext.language.as_str(), columns), get_tsquery(query, fulltext.language.as_str(), value))
+}
+
+/* ************************************************************************************* */
+
+// '_rank' (ts_rank) and , when enabled , '_headline' (ts_headline) of every row , returned next to the rows (see page_ops)
+// the values are bound in 'query' , which must be a copy of the where clause only used by the select
+// (the count queries would fail on the unused parameters)
+pub fn get_fulltext_select(query: &mut SqlQuery, filters: &SearchFilters) -> Option<Vec<String>> {
+    if filters.pattern_match != "fulltext" {
+        return None
+    }
+
+    let mut terms = vec![];
+    get_positive_terms(filters.search.as_ref()?, &mut terms);
+    let columns = get_document_columns(&filters.fulltext, &filters.searchable_columns);
+    if terms.is_empty() || columns.is_empty() {
+        return None
+    }
+
+    let language = filters.fulltext.language.as_str();
+    let tsquery = terms.iter()
+        .map(|t| get_tsquery(query, language, t))
+        .collect::<Vec<String>>()
+        .join(" || ");
+
+    let mut select_columns = vec![format!("ts_rank({}, {}) AS \"_rank\"", get_document(language, &columns), tsquery)];
+    if filters.fulltext.headline {
+        select_columns.push(format!("ts_headline('{}'::regconfig, {}, {}, 'MaxFragments=2, MinWords=5, MaxWords=20') AS \"_headline\"", language, get_document_text(&columns), tsquery));
+    }
+
+    Some(select_columns)
+}
@@ -0,0 +1,46 @@
+use crate::data_types::{CustomError, SearchFilters, TableConfig};
+use crate::query_ops::SqlQuery;
+use crate::search_ops::{get_positive_terms, is_text_search_column};
+use crate::string_ops::quote_identifier;
+
+// 'fuzzy' search mode (pg_trgm) : a term matches a text column when their trigram similarity is above
+// pg_trgm.similarity_threshold (SEARCH.FUZZY_THRESHOLD , default 0.3) >> mistyped hashes and identifiers
+//
+//   "md5" % $1
+//
+// the operator (not 'similarity() >= x') is used , so a GIN / GiST 'gin_trgm_ops' index is used by postgres
+
+// pg_trgm has to be installed in the database of the table (CREATE EXTENSION pg_trgm)
+pub fn check_fuzzy_search(table: &TableConfig, pattern_match: &str) -> Result<(), CustomError> {
+    if pattern_match == "fuzzy" && !table.has_pg_trgm {
+        return Err(CustomError::ValidationError(format!("fuzzy search is not available for table ({}) , pg_trgm is not installed", table.short_name)))
+    }
+    Ok(())
+}
+
+// '_similarity' of every row (best similarity of the terms over the text columns) , returned next to the rows (see page_ops)
+// the rows are ordered by it , before the order chosen in the UI
+// the values are bound in 'query' , which must be a copy of the where clause only used by the select
+pub fn get_fuzzy_select(query: &mut SqlQuery, filters: &SearchFilters) -> Option<Vec<String>> {
+    if filters.pattern_match != "fuzzy" {
+        return None
+    }
+
+    let mut terms = vec![];
+    get_positive_terms(filters.search.as_ref()?, &mut terms);
+    let columns: Vec<String> = filters.columns.iter()
+        .filter(|c| filters.searchable_columns.contains(&c.name) && !c.masked && is_text_search_column(c))
+        .map(|c| format!("{}::text", quote_identifier(c.name.as_str())))
+        .collect();
+    if terms.is_empty() || columns.is_empty() {
+        return None
+    }
+
+    let mut similarities = vec![];
+    for term in &terms {
+        let placeholder = query.bind(term.to_string());
+        similarities.extend(columns.iter().map(|c| format!("similarity({}, {})", c, placeholder)));
+    }
+
+    Some(vec![format!("GREATEST({}) AS \"_similarity\"", similarities.join(", "))])
+}
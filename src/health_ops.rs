@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+use deadpool_postgres::Pool;
+use lazy_static::lazy_static;
+use tera::Tera;
+use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+use crate::data_types::{DbPools, HealthCheck, ReadinessResponse};
+use crate::log_ops::get_elapsed_ms;
+use crate::retention_ops::{EXPORT_DIR, PARTIAL_EXPORT_SUFFIX};
+
+// probes for systemd and load balancers , both reachable without a user
+//   GET /healthz : the process answers
+//   GET /readyz  : every db pool gives a connection that runs 'SELECT 1' , data_dir is writable , the templates are loaded
+// the checks run concurrently , each one within CHECK_TIMEOUT
+// /readyz is public : the response only says what failed , the reason is in the logs , and the checks run at most
+// once per READINESS_TTL whatever the request rate (a pool connection or a file in data_dir per hit otherwise)
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+const TEMPLATES: [&str; 2] = ["index.html", "login.html"];
+const READINESS_TTL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref LAST_READINESS: Mutex<Option<(Instant, ReadinessResponse)>> = Mutex::new(None);
+}
+
+async fn run_check(name: String, check: impl std::future::Future<Output = Result<(), String>>) -> HealthCheck {
+    let started_at = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(r) => r,
+        Err(_) => {
+            warn!("readiness check ({}) : no answer within {}s", name, CHECK_TIMEOUT.as_secs());
+            Err(format!("no answer within {}s", CHECK_TIMEOUT.as_secs()))
+        }
+    };
+    HealthCheck {
+        name,
+        ok: result.is_ok(),
+        duration_ms: get_elapsed_ms(started_at),
+        error: result.err(),
+    }
+}
+
+async fn check_pool(name: &str, pool: &Pool) -> Result<(), String> {
+    let client = match pool.get().await {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("readiness check (pool:{}) : could not get a connection : {}", name, e);
+            return Err("could not get a connection".to_string())
+        }
+    };
+    match client.query_one("SELECT 1", &[]).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!("readiness check (pool:{}) : SELECT 1 failed : {}", name, e.as_db_error().map_or(e.to_string(), |d| d.message().to_string()));
+            Err("SELECT 1 failed".to_string())
+        }
+    }
+}
+
+// a hidden partial file : never listed as an export , swept if left behind
+async fn check_export_dir() -> Result<(), String> {
+    let probe_path = format!("{}/.readyz_{}{}", EXPORT_DIR, Uuid::new_v4(), PARTIAL_EXPORT_SUFFIX);
+    if let Err(e) = fs::write(probe_path.as_str(), b"readyz").await {
+        warn!("readiness check (data_dir) : could not write ({}) : {}", probe_path, e);
+        return Err(format!("({}) is not writable", EXPORT_DIR))
+    }
+    match fs::remove_file(probe_path.as_str()).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!("readiness check (data_dir) : could not remove ({}) : {}", probe_path, e);
+            Err(format!("files of ({}) cannot be removed", EXPORT_DIR))
+        }
+    }
+}
+
+fn check_templates(tera: &Tera) -> Result<(), String> {
+    let names: Vec<&str> = tera.get_template_names().collect();
+    match TEMPLATES.iter().find(|t| !names.contains(t)) {
+        Some(missing) => {
+            warn!("readiness check (templates) : ({}) is not loaded", missing);
+            Err(format!("template ({}) is not loaded", missing))
+        },
+        None => Ok(()),
+    }
+}
+
+// the last response is kept for READINESS_TTL , concurrent requests wait for the checks in flight
+pub async fn get_readiness(pools: &DbPools, tera: &Tera) -> ReadinessResponse {
+    let mut last_readiness = LAST_READINESS.lock().await;
+    if let Some((checked_at, readiness)) = last_readiness.as_ref() {
+        if checked_at.elapsed() < READINESS_TTL {
+            return readiness.clone()
+        }
+    }
+    let readiness = run_readiness_checks(pools, tera).await;
+    *last_readiness = Some((Instant::now(), readiness.clone()));
+    readiness
+}
+
+async fn run_readiness_checks(pools: &DbPools, tera: &Tera) -> ReadinessResponse {
+    let mut pool_names: Vec<&String> = pools.pools.keys().collect();
+    pool_names.sort();
+
+    let pool_checks = futures::future::join_all(pool_names.into_iter().map(|name| {
+        run_check(format!("pool:{}", name), check_pool(name, &pools.pools[name]))
+    }));
+    let (mut checks, export_dir_check, templates_check) = futures::join!(
+        pool_checks,
+        run_check("data_dir".to_string(), check_export_dir()),
+        run_check("templates".to_string(), async { check_templates(tera) }),
+    );
+    checks.push(export_dir_check);
+    checks.push(templates_check);
+
+    let ready = checks.iter().all(|c| c.ok);
+    ReadinessResponse {
+        status: if ready { "ready" } else { "not_ready" }.to_string(),
+        checks,
+    }
+}
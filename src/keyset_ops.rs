@@ -0,0 +1,331 @@
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use uuid::Uuid;
+use crate::data_types::{CustomError, KeysetCursor, PageRequest, SortColumn, TableConfig};
+use crate::query_ops::{get_order_by_clause, SqlQuery};
+use crate::string_ops::quote_identifier;
+
+// keyset (cursor) pagination : deep pages of large tables without 'OFFSET n' (postgres reads and drops n rows)
+//
+//   WHERE <search> AND ( "random_num", "id" ) > ( $3::text::int4, $4::text::int4 ) ORDER BY "random_num" ASC, "id" ASC LIMIT 10
+//
+// every page returns 'next_cursor' / 'prev_cursor' , the UI sends one back ('cursor') with the 'start' of that page
+// without a (matching) cursor the page is read with OFFSET , so DataTables keeps working as before
+// (first page , jumping to any page , changing the search or the sort)
+//
+// a cursor is signed (HMAC , key of the process) : its values end up in the WHERE clause , the client may not edit them
+// an edited cursor , like a cursor of a restarted server , is not used >> OFFSET
+
+type HmacSha256 = Hmac<Sha256>;
+
+lazy_static! {
+    static ref CURSOR_SECRET: Vec<u8> = format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple()).into_bytes();
+}
+
+// the sort of the page followed by the key column (tie breaker) , None when keyset pagination cannot be used :
+// no 'keyset' for the table , rows ordered by a score ('_rank' , '_similarity') or by a nullable column ,
+// a masked key column (the cursors would carry its values)
+pub fn get_keyset_sort(table: &TableConfig, sort_columns: &[SortColumn], score_order: bool) -> Option<Vec<SortColumn>> {
+    let keyset = table.keyset.as_ref()?;
+    if score_order || table.column_info.iter().any(|c| c.name == keyset.column && c.masked) {
+        return None
+    }
+
+    let mut sort = vec![];
+    for sort_column in sort_columns {
+        // NULLs cannot be compared , a cursor could not point past them
+        let column = table.column_info.iter().find(|c| c.name == sort_column.name)?;
+        if column.is_nullable {
+            return None
+        }
+        sort.push(sort_column.clone());
+        // the key is unique , the columns after it do not change the order
+        if sort_column.name == keyset.column {
+            return Some(sort)
+        }
+    }
+    sort.push(SortColumn { name: keyset.column.to_string(), descending: false });
+    Some(sort)
+}
+
+// a cursor is only valid for the search and the sort it was made for
+fn get_fingerprint(where_clause: &SqlQuery, sort: &[SortColumn]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(where_clause.sql.as_bytes());
+    for param in &where_clause.params {
+        hasher.update([0]);
+        hasher.update(param.as_bytes());
+    }
+    for sort_column in sort {
+        hasher.update([0]);
+        hasher.update(sort_column.name.as_bytes());
+        hasher.update([sort_column.descending as u8]);
+    }
+    hex::encode(&hasher.finalize()[..8])
+}
+
+fn get_cursor_signer(payload: &[u8]) -> Option<HmacSha256> {
+    let mut mac = HmacSha256::new_from_slice(&CURSOR_SECRET).ok()?;
+    mac.update(payload);
+    Some(mac)
+}
+
+// <hex json>.<hex hmac>
+fn encode_cursor(cursor: &KeysetCursor) -> Option<String> {
+    let payload = serde_json::to_vec(cursor).ok()?;
+    let sig = get_cursor_signer(&payload)?.finalize().into_bytes();
+    Some(format!("{}.{}", hex::encode(&payload), hex::encode(sig)))
+}
+
+// None when the signature does not match : the values of the cursor are never used
+fn decode_cursor(token: &str) -> Result<Option<KeysetCursor>, CustomError> {
+    let invalid = || CustomError::ValidationError("invalid cursor".to_string());
+    let (payload, sig) = token.split_once('.').ok_or_else(invalid)?;
+    let (payload, sig) = match (hex::decode(payload), hex::decode(sig)) {
+        (Ok(p), Ok(s)) => (p, s),
+        _ => return Err(invalid())
+    };
+    // constant time comparison
+    if get_cursor_signer(&payload).is_none_or(|mac| mac.verify_slice(&sig).is_err()) {
+        warn!("invalid cursor signature , OFFSET is used");
+        return Ok(None)
+    }
+    serde_json::from_slice(&payload).map(Some).map_err(|_| invalid())
+}
+
+// a cursor of another page , search or sort (the search changed since the last draw) is ignored >> OFFSET
+pub fn get_keyset_cursor(page: &PageRequest, sort: &[SortColumn], where_clause: &SqlQuery) -> Result<Option<KeysetCursor>, CustomError> {
+    if page.cursor.is_empty() {
+        return Ok(None)
+    }
+
+    let cursor = match decode_cursor(page.cursor.as_str())? {
+        Some(c) => c,
+        None => return Ok(None)
+    };
+
+    if cursor.start != page.start || cursor.length != page.length || cursor.values.len() != sort.len() || cursor.fingerprint != get_fingerprint(where_clause, sort) {
+        debug!("cursor does not match the requested page , OFFSET is used");
+        return Ok(None)
+    }
+
+    Ok(Some(cursor))
+}
+
+/* ************************************************************************************* */
+
+// a previous page is read backwards (reversed sort) , its rows are put back in order by get_page_cursors
+pub fn get_keyset_order_by(sort: &[SortColumn], cursor: Option<&KeysetCursor>) -> String {
+    let backward = cursor.is_some_and(|c| c.backward);
+    let sort: Vec<SortColumn> = sort.iter()
+        .map(|s| SortColumn { name: s.name.to_string(), descending: s.descending != backward })
+        .collect();
+    get_order_by_clause(&sort)
+}
+
+// the sort values of every row , read as text (exact round trip through '$n::text::<type>')
+pub fn get_cursor_columns(sort: &[SortColumn]) -> Vec<String> {
+    sort.iter().enumerate()
+        .map(|(i, s)| format!("{}::text AS \"_cursor_{}\"", quote_identifier(s.name.as_str()), i))
+        .collect()
+}
+
+// rows after (or before) the values of the cursor , in the sort of the page
+// the values are bound in 'query' , which must be a copy of the where clause only used by the select
+pub fn add_keyset_condition(query: &mut SqlQuery, table: &TableConfig, sort: &[SortColumn], cursor: &KeysetCursor) {
+    let mut names = vec![];
+    let mut placeholders = vec![];
+    for (sort_column, value) in sort.iter().zip(&cursor.values) {
+        let udt_name = table.column_info.iter()
+            .find(|c| c.name == sort_column.name)
+            .map_or("text", |c| c.udt_name.as_str());
+        names.push(quote_identifier(sort_column.name.as_str()));
+        placeholders.push(format!("{}::text::{}", query.bind(value.to_string()), udt_name));
+    }
+
+    let get_operator = |s: &SortColumn| if s.descending != cursor.backward { "<" } else { ">" };
+
+    // same direction for every column >> row comparison , served by a composite index
+    let condition = if sort.iter().all(|s| s.descending == sort[0].descending) {
+        format!(" ( {} ) {} ( {} ) ", names.join(", "), get_operator(&sort[0]), placeholders.join(", "))
+    } else {
+        // mixed directions : (a > x) OR (a = x AND b < y) OR ...
+        let alternatives: Vec<String> = (0..sort.len()).map(|i| {
+            let mut parts: Vec<String> = (0..i).map(|j| format!("{} = {}", names[j], placeholders[j])).collect();
+            parts.push(format!("{} {} {}", names[i], get_operator(&sort[i]), placeholders[i]));
+            format!("( {} )", parts.join(" AND "))
+        }).collect();
+        format!(" ( {} ) ", alternatives.join(" OR "))
+    };
+
+    query.sql = match query.sql.strip_prefix("WHERE ") {
+        Some(search) => format!("WHERE ( {} ) AND {}", search, condition),
+        None => format!("WHERE {}", condition),
+    };
+}
+
+/* ************************************************************************************* */
+
+fn take_cursor_values(row: &mut Value, count: usize) -> Option<Vec<String>> {
+    let row = row.as_object_mut()?;
+    (0..count)
+        .map(|i| match row.remove(format!("_cursor_{}", i).as_str()) {
+            Some(Value::String(v)) => Some(v),
+            _ => None,
+        })
+        .collect()
+}
+
+// removes the '_cursor_n' columns from the rows and returns (next_cursor , prev_cursor)
+// tables with fewer (filtered) rows than 'min_rows' do not get cursors
+pub fn get_page_cursors(table: &TableConfig, sort: &[SortColumn], cursor: Option<&KeysetCursor>, page: &PageRequest, where_clause: &SqlQuery, rows: &mut [Value], records_filtered: i64) -> (Option<String>, Option<String>) {
+    if cursor.is_some_and(|c| c.backward) {
+        rows.reverse();
+    }
+
+    let values: Vec<Option<Vec<String>>> = rows.iter_mut().map(|r| take_cursor_values(r, sort.len())).collect();
+
+    let min_rows = table.keyset.as_ref().map_or(i64::MAX, |k| k.min_rows);
+    if records_filtered < min_rows || page.length == 0 {
+        return (None, None)
+    }
+
+    let fingerprint = get_fingerprint(where_clause, sort);
+    let make_cursor = |start: u32, values: &Option<Vec<String>>, backward: bool| {
+        encode_cursor(&KeysetCursor {
+            start,
+            length: page.length,
+            fingerprint: fingerprint.to_string(),
+            values: values.clone()?,
+            backward,
+        })
+    };
+
+    let next_start = page.start.saturating_add(page.length);
+    let next_cursor = match values.last() {
+        Some(last) if rows.len() == page.length as usize && (next_start as i64) < records_filtered => make_cursor(next_start, last, false),
+        _ => None,
+    };
+    let prev_cursor = match values.first() {
+        Some(first) if page.start >= page.length => make_cursor(page.start - page.length, first, true),
+        _ => None,
+    };
+
+    (next_cursor, prev_cursor)
+}
+
+/* ************************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::data_types::ColumnInfo;
+
+    fn get_table() -> TableConfig {
+        let mut table: TableConfig = serde_json::from_value(json!({
+            "short_name": "table1",
+            "title": "table1",
+            "backend_table": "t_random",
+            "keyset": {"column": "id", "min_rows": 0},
+        })).unwrap();
+        table.column_info = ["id", "random_num"].iter()
+            .map(|name| ColumnInfo { name: name.to_string(), data_type: "integer".to_string(), udt_name: "int4".to_string(), is_nullable: false, masked: false })
+            .collect();
+        table
+    }
+
+    fn get_sort() -> Vec<SortColumn> {
+        vec![SortColumn { name: "random_num".to_string(), descending: true }, SortColumn { name: "id".to_string(), descending: false }]
+    }
+
+    fn get_where_clause(search: &str) -> SqlQuery {
+        let mut query = SqlQuery::new();
+        query.sql = format!("WHERE \"md5\" ILIKE {}", query.bind(search.to_string()));
+        query
+    }
+
+    fn get_page(start: u32, cursor: &str) -> PageRequest {
+        PageRequest { start, length: 2, cursor: cursor.to_string() }
+    }
+
+    // the second page of a table of 10 rows , its rows carry their sort values as '_cursor_n'
+    fn get_cursors() -> (Option<String>, Option<String>) {
+        let mut rows = vec![
+            json!({"id": 3, "random_num": 900, "_cursor_0": "900", "_cursor_1": "3"}),
+            json!({"id": 7, "random_num": 850, "_cursor_0": "850", "_cursor_1": "7"}),
+        ];
+        let cursors = get_page_cursors(&get_table(), &get_sort(), None, &get_page(2, ""), &get_where_clause("%abc%"), &mut rows, 10);
+        assert_eq!(rows[0], json!({"id": 3, "random_num": 900}));
+        cursors
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let (next_cursor, prev_cursor) = get_cursors();
+        let where_clause = get_where_clause("%abc%");
+
+        let next = get_keyset_cursor(&get_page(4, next_cursor.unwrap().as_str()), &get_sort(), &where_clause).unwrap().unwrap();
+        assert_eq!((next.start, next.length, next.backward), (4, 2, false));
+        assert_eq!(next.values, vec!["850".to_string(), "7".to_string()]);
+
+        let prev = get_keyset_cursor(&get_page(0, prev_cursor.unwrap().as_str()), &get_sort(), &where_clause).unwrap().unwrap();
+        assert_eq!((prev.start, prev.backward), (0, true));
+        assert_eq!(prev.values, vec!["900".to_string(), "3".to_string()]);
+    }
+
+    #[test]
+    fn cursor_of_another_search_sort_or_page_is_ignored() {
+        let next_cursor = get_cursors().0.unwrap();
+        let page = get_page(4, next_cursor.as_str());
+
+        assert!(get_keyset_cursor(&page, &get_sort(), &get_where_clause("%xyz%")).unwrap().is_none());
+        let mut sort = get_sort();
+        sort[0].descending = false;
+        assert!(get_keyset_cursor(&page, &sort, &get_where_clause("%abc%")).unwrap().is_none());
+        assert!(get_keyset_cursor(&get_page(6, next_cursor.as_str()), &get_sort(), &get_where_clause("%abc%")).unwrap().is_none());
+    }
+
+    // a cursor that is not used : the page is read with OFFSET
+    fn is_ignored(cursor: &str) -> bool {
+        get_keyset_cursor(&get_page(4, cursor), &get_sort(), &get_where_clause("%abc%")).unwrap().is_none()
+    }
+
+    #[test]
+    fn tampered_cursor_is_never_used() {
+        let next_cursor = get_cursors().0.unwrap();
+        let (payload, sig) = next_cursor.split_once('.').unwrap();
+        let mut cursor: KeysetCursor = serde_json::from_slice(&hex::decode(payload).unwrap()).unwrap();
+
+        // an edited key value with a recomputed fingerprint , with the old signature or a signature made with another key
+        cursor.values[1] = "0".to_string();
+        cursor.fingerprint = get_fingerprint(&get_where_clause("%abc%"), &get_sort());
+        let edited = serde_json::to_vec(&cursor).unwrap();
+        assert!(is_ignored(format!("{}.{}", hex::encode(&edited), sig).as_str()));
+        let mut mac = HmacSha256::new_from_slice(b"0123456789abcdef0123456789abcdef").unwrap();
+        mac.update(&edited);
+        assert!(is_ignored(format!("{}.{}", hex::encode(&edited), hex::encode(mac.finalize().into_bytes())).as_str()));
+
+        // malformed
+        let where_clause = get_where_clause("%abc%");
+        for token in [hex::encode(&edited), "not-hex".to_string(), format!("{}.xyz", payload)] {
+            assert!(matches!(get_keyset_cursor(&get_page(4, token.as_str()), &get_sort(), &where_clause), Err(CustomError::ValidationError(_))));
+        }
+        assert!(is_ignored(""));
+        assert!(!is_ignored(next_cursor.as_str()));
+    }
+
+    #[test]
+    fn keyset_condition_binds_the_cursor_values() {
+        let (next_cursor, _) = get_cursors();
+        let mut query = get_where_clause("%abc%");
+        let cursor = get_keyset_cursor(&get_page(4, next_cursor.unwrap().as_str()), &get_sort(), &query).unwrap().unwrap();
+        add_keyset_condition(&mut query, &get_table(), &get_sort(), &cursor);
+        assert_eq!(query.params, vec!["%abc%".to_string(), "850".to_string(), "7".to_string()]);
+        assert!(query.sql.contains("\"random_num\" < $2::text::int4"));
+        assert!(query.sql.contains("\"id\" > $3::text::int4"));
+    }
+}
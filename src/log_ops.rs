@@ -0,0 +1,82 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+use actix_web::http::header::ContentType;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use tracing::{debug, error};
+use tracing_subscriber::EnvFilter;
+use crate::data_types::{CustomError, LogConfig, LogFormat};
+use crate::db_ops::get_optional_env_var;
+
+// structured logs (tracing) , every event of a request carries the fields of its span (see with_request_id)
+//   LOG.LEVEL=info      : error | warn | info | debug | trace , or a filter >> example : 'info,rust_datatable=debug'
+//   LOG.FORMAT=pretty   : pretty | json
+// RUST_LOG , when set , overrides LOG.LEVEL
+//
+// search values never reach the logs : SQL is logged at 'debug' with its parameters redacted ,
+// tokio_postgres (which logs the bound values at 'debug') stays at 'info' unless LOG.LEVEL names it
+
+pub fn load_log_config() -> Result<LogConfig, CustomError> {
+    let level = get_optional_env_var::<String>("LOG.LEVEL")?.unwrap_or_else(|| "info".to_string());
+    let format = match get_optional_env_var::<String>("LOG.FORMAT")?.unwrap_or_else(|| "pretty".to_string()).as_str() {
+        "pretty" => LogFormat::Pretty,
+        "json" => LogFormat::Json,
+        other => return Err(CustomError::ConfigError(format!("invalid LOG.FORMAT ({}) , valid values : 'pretty' , 'json'", other)))
+    };
+
+    Ok(LogConfig { level, format })
+}
+
+pub fn init_logging(config: &LogConfig) -> Result<(), CustomError> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) => EnvFilter::try_new(directives.as_str()),
+        Err(_) if config.level.contains("tokio_postgres") => EnvFilter::try_new(config.level.as_str()),
+        Err(_) => EnvFilter::try_new(format!("{},tokio_postgres=info", config.level)),
+    };
+    let filter = match filter {
+        Ok(f) => f,
+        Err(e) => return Err(CustomError::ConfigError(format!("invalid LOG.LEVEL ({}) : {}", config.level, e)))
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_target(false);
+    let result = match config.format {
+        LogFormat::Pretty => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).with_span_list(false).try_init(),
+    };
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(CustomError::ConfigError(format!("could not set up logging : {}", e)))
+    }
+}
+
+// bound values are search values (or cursors built from rows) , only their length is logged
+// example : ["<5 chars>", "<12 chars>"]
+pub fn get_redacted_params(params: &[String]) -> Vec<String> {
+    params.iter().map(|p| format!("<{} chars>", p.chars().count())).collect()
+}
+
+pub fn get_elapsed_ms(started_at: Instant) -> u64 {
+    started_at.elapsed().as_millis() as u64
+}
+
+// the result of a query with the time it took
+pub async fn timed<T>(future: impl Future<Output = Result<T, CustomError>>) -> Result<(T, Duration), CustomError> {
+    let started_at = Instant::now();
+    let value = future.await?;
+    Ok((value, started_at.elapsed()))
+}
+
+// pages are serialized here (not by HttpResponse::json) to log the time it takes
+pub fn get_json_response<T: Serialize>(response: &T) -> HttpResponse {
+    let started_at = Instant::now();
+    match serde_json::to_string(response) {
+        Ok(body) => {
+            debug!(serialize_ms = get_elapsed_ms(started_at), bytes = body.len(), "response serialized");
+            HttpResponse::Ok().content_type(ContentType::json()).body(body)
+        },
+        Err(e) => {
+            error!("could not serialize the response : {}", e);
+            CustomError::DatabaseError("could not serialize the rows".to_string()).error_response()
+        }
+    }
+}
@@ -1,327 +1,403 @@
 // main.rs
 
-use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder, http, ResponseError, HttpRequest};
-use actix_web::{error, Result};
-use actix_cors::Cors;
-use actix_web::http::header::CONTENT_TYPE;
-
-use actix_web::web::get;
-use serde::{Deserialize, Serialize};
-use tokio_postgres::{NoTls, Error, Row};
-use deadpool_postgres::Client;
-use tokio::runtime::Runtime;
-use serde_json::json;
-use regex::Regex;
-
-use async_std::task;
-use std::time::Duration;
-use actix_web::web::Query;
-use futures::{join, select, StreamExt};
-use futures::future::FutureExt;
-use futures::stream;
-use futures::pin_mut;
-use futures::try_join;
-use async_std;
-use deadpool_postgres::{Config, Pool};
-use derive_more::{Display, From};
+use actix_web::{post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::error;
+use actix_web::http::header;
+use actix_web::middleware::from_fn;
+
+use deadpool_postgres::Pool;
 use dotenv::dotenv;
 use tera::{Context, Tera};
-use actix_web::{web::Data};
-use actix_files::Files;
-use csv::Writer;
-use uuid::Uuid;
-use std::error::Error as StdError;
-use serde_json::Error as SerdeError;
+use tracing::{error, info, warn};
 use serde_json::error::Category;
-use sqlx::Value;
 use std::path::Path;
 
 mod string_ops;
 mod db_ops;
 mod data_types;
-
-use crate::data_types::{CustomError, CustomErrorType, Data1, Data2, ExportData, JsonResponseWithCSVExportData};
-use crate::data_types::{FormData, GenericError, JsonResponse, SearchStringData};
-use crate::db_ops::{export_table_to_csv, get_backend_table, get_backend_table_columns, get_count_of_records};
-use crate::db_ops::{fetch};
-use crate::db_ops::{get_db_pool_for_table, get_inner_query, get_table_column_mapping};
-use crate::string_ops::{remove_leading_and_trailing_spaces, sanitize_string, split_string};
-
-#[post("/echo")]
-async fn echo(req_body: String) -> impl Responder {
-    HttpResponse::Ok().body(req_body)
-}
-
+mod registry_ops;
+mod query_ops;
+mod search_ops;
+mod fulltext_ops;
+mod fuzzy_ops;
+mod keyset_ops;
+mod page_ops;
+mod api_ops;
+mod row_ops;
+mod datatables_ops;
+mod request_ops;
+mod export_ops;
+mod writer_ops;
+mod retention_ops;
+mod download_ops;
+mod auth_ops;
+mod access_ops;
+mod audit_ops;
+mod log_ops;
+mod metrics_ops;
+mod health_ops;
+
+use crate::data_types::{ApiRowsRequest, ApiTableSchema, ApiTablesResponse, ApiTokenRequest, ApiTokenResponse, Audit, AuditEvent, Auth, ColumnSearch, CustomError, DataTablesResponse, DbPools, DownloadConfig, DownloadParams, ExportData, ExportJobs, ExportListResponse, ExportPurgeParams, ExportPurgeResponse, ExportRetention, FormData, JsonResponseWithCSVExportData, LoginForm, LoginParams, PageRequest, Permission, SearchFilters, TableConfig, TableRegistry};
+use crate::datatables_ops::{get_search_description, get_search_filters, parse_datatables_request};
+use crate::db_ops::{get_db_pool_for_table, make_db_pools};
+use crate::query_ops::{get_sort_columns, get_where_clause, SqlQuery};
+use crate::page_ops::get_table_page;
+use crate::api_ops::{get_api_rows, get_api_search_description, get_api_table_schema, get_api_tables};
+use crate::search_ops::parse_search_expression;
+use crate::fulltext_ops::get_fulltext_config;
+use crate::fuzzy_ops::check_fuzzy_search;
+use crate::registry_ops::{load_table_registry, validate_table_registry};
+use crate::export_ops::{cancel_export_job, get_export_job, start_export_job};
+use crate::retention_ops::{check_export_quota, delete_export, get_export_list, get_export_metadata, load_export_retention, purge_exports, start_export_sweeper};
+use crate::download_ops::{get_client_identity, get_download_response, load_download_config};
+use crate::request_ops::{get_request_id, load_cors_origins, load_proxy_config, make_cors, set_request_table, with_request_id};
+use crate::auth_ops::{create_api_token, get_current_user, get_safe_next, hash_password, load_auth_config, login, logout, make_auth, with_auth, SESSION_COOKIE};
+use crate::access_ops::{can_admin_exports, check_access_rules, get_table_view_for_user, get_visible_tables};
+use crate::log_ops::{get_json_response, init_logging, load_log_config};
+use crate::metrics_ops::get_metrics;
+use crate::health_ops::get_readiness;
+use crate::audit_ops::{check_audit_access, finish_audit_event, get_audit_page, get_audit_page_request, load_audit_config, make_audit, start_audit_event, AUDIT_COLUMNS};
+
+const BIND_ADDRESS: &str = "0.0.0.0:5050";
 
 #[post("/query")]
-async fn query_data(form: web::Form<FormData>) -> impl Responder {
-
-    // let form_data = &form.into_inner();
-
-    let mut draw: u32 = 0;
-    let mut length: u32 = 0;
-    let mut start: u32 = 0;
-    let mut search_string = "".to_string();
-    let mut exact_search = "".to_string();
-
-    let mut search_strings:Vec<String> = vec![];
-
-    let mut sanitized_search_strings: Vec<String> = vec![];
-
-    let mut search_string_for_reference = "".to_string();
-
-    let mut search_type = "or".to_string();
-
-    let mut sanitized_search_strings = vec![];
-    let mut search_strings_with_leading_and_trailing_spaces_removed = vec![];
-
-    let mut search_string_for_reference = "".to_string();
-
-    let mut sort_column_index = "".to_string();
-    let mut sort_column_name = "".to_string();
-    let mut sort_column_order = "".to_string();
-
-    let mut table_short_name = "".to_string();
-
-    // Access all form fields dynamically
-    for (key, value) in &form.fields {
-        println!("Field: {} : {}", key, value);
-
-        if key == "length" {
-            length = value.parse::<u32>().unwrap();
-        }
-        if key == "draw" {
-            draw = value.parse::<u32>().unwrap() as u32;
-        }
-        if key == "start" {
-            start = value.parse::<u32>().unwrap() as u32;
-        }
-
-        // -------------- column sorting | start ------------
-        if key == "order[0][dir]" {
-            sort_column_order = value.to_string(); // 'asc' or 'desc'
+async fn query_data(req: HttpRequest, form: web::Form<FormData>, registry: web::Data<TableRegistry>, pools: web::Data<DbPools>) -> impl Responder {
+    let mut event = start_audit_event(&req, "query");
+    match get_datatables_response(&req, &form, &registry, &pools, &mut event).await {
+        Ok(response) => {
+            finish_audit_event(event, Some(response.data.len() as i64), "ok");
+            get_json_response(&response)
+        },
+        Err(e) => {
+            finish_audit_event(event, None, e.code());
+            // 'draw' is echoed back even when the rest of the request could not be parsed
+            let draw = form.fields.get("draw").and_then(|d| d.trim().parse::<u32>().ok()).unwrap_or(0);
+            get_datatables_error_response(draw, &e)
         }
+    }
+}
 
-        if key == "order[0][column]" {
-            sort_column_index = value.to_string(); // '0' or '1'  or '2' etc...
-        }
-        // -------------- column sorting | end ------------
-
-        if key == "search[value]" {
-            let search_string = value.to_string();
-            search_string_for_reference = value.to_string();
-            println!("search_string_for_reference: {}", search_string_for_reference);
-
-            if !search_string.is_empty() {
-                // you cannot have both AND ('+') and OR ('|') based search
-                // it should be either '+' or '|'
-                if search_string.contains("+") && search_string.contains("|") {
-                    println!("error : cannot search, search_string contains both '+' (AND search) and '|' (OR search) !");
-                    return HttpResponse::BadRequest().finish()
-                }
-            }
+async fn get_datatables_response(req: &HttpRequest, form: &FormData, registry: &TableRegistry, pools: &DbPools, event: &mut AuditEvent) -> Result<DataTablesResponse, CustomError> {
+    let request = parse_datatables_request(form).await?;
+    event.entry.table_name = request.table_name.to_string();
 
-            if search_string.contains("+") { // AND search
-                search_strings = split_string(search_string.as_str(), "+").await;
-                search_type = "and".to_string();
-            } else if search_string.contains("|") { // OR search
-                search_strings = split_string(search_string.as_str(), "|").await;
-                search_type = "or".to_string();
-            } else { // default search, which means (search_string) does not contain either '+' or '|'
-                search_type = "or".to_string();
-                search_strings = Vec::from([search_string]);
-            }
+    // the table as the user may see it : row filter and masked columns (see access_ops)
+    let table = &get_table_view_for_user(registry, request.table_name.as_str(), get_current_user(req).as_ref(), Permission::View)?;
+    set_request_table(req, table.short_name.as_str());
+    event.entry.search = get_search_description(table, &request);
 
-            // below sanitize_string(...) will ensure to remove
-            // all characters except these : "_./-@,#:;"
-            // so that way, DB does not get any characters that are not needed
-            // let mut search_strings_with_leading_and_trailing_spaces_removed: Vec<String> = search_strings.iter().map(|s| remove_leading_and_trailing_spaces(s)).collect();
+    let search_filters = get_search_filters(table, &request).await?;
+    event.entry.pattern_match = search_filters.pattern_match.to_string();
 
-            let futures_1: Vec<_> = search_strings.iter().map(|s| async {
-                remove_leading_and_trailing_spaces(s).await
-            }).collect();
-            search_strings_with_leading_and_trailing_spaces_removed = futures::future::join_all(futures_1).await;
+    let sort_columns = get_sort_columns(table, &request.order, &request.columns).await?;
 
-            println!("@ length of search_strings_with_leading_and_trailing_spaces_removed >> {}", search_strings_with_leading_and_trailing_spaces_removed.len());
+    let my_db_pool = get_db_pool_for_table(pools, table).await?;
 
-            // //////////////////////////
+    let page = PageRequest {
+        start: request.start,
+        length: request.length,
+        cursor: request.cursor.to_string(),
+    };
 
-            // sanitized_search_strings = search_strings_with_leading_and_trailing_spaces_removed.iter().map(|s| sanitize_string(s)).collect();
+    let table_page = get_table_page(table, &my_db_pool, &search_filters, &sort_columns, &[], &page).await?;
+
+    Ok(DataTablesResponse {
+        draw: request.draw,
+        records_total: table_page.records_total,
+        records_filtered: table_page.records_filtered,
+        data: table_page.rows,
+        next_cursor: table_page.next_cursor,
+        prev_cursor: table_page.prev_cursor,
+        scores: table_page.scores,
+        error: None,
+        request_id: None,
+    })
+}
 
-            let futures_2: Vec<_> = search_strings_with_leading_and_trailing_spaces_removed.iter().map(|s| async {
-                sanitize_string(s).await
-            }).collect();
-            sanitized_search_strings = futures::future::join_all(futures_2).await;
+// DataTables shows the 'error' field of a (200) response to the user
+fn get_datatables_error_response(draw: u32, e: &CustomError) -> HttpResponse {
+    let request_id = get_request_id();
+    e.log();
+    let response = DataTablesResponse {
+        draw,
+        records_total: 0,
+        records_filtered: 0,
+        data: vec![],
+        next_cursor: None,
+        prev_cursor: None,
+        scores: None,
+        error: Some(e.to_string()),
+        request_id: Some(request_id),
+    };
+    HttpResponse::Ok().json(response)
+}
 
-            println!("@ length of sanitized_search_strings >> {}", sanitized_search_strings.len());
+async fn index(req: HttpRequest, tera: web::Data<Tera>, registry: web::Data<TableRegistry>) -> Result<HttpResponse, CustomError> {
+    let mut context = Context::new();
+    context.insert("title", "Data Table");
+    context.insert("message", "PS Table");
+    let user = get_current_user(&req);
+    context.insert("tables", &get_visible_tables(&registry, user.as_ref()));
+    context.insert("username", &user.map(|u| u.username));
+    context.insert("query_url", "/query");
+    context.insert("default_order", "asc");
+
+    let rendered = match tera.render("index.html", &context) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("could not render index.html : {:?}", e);
+            return Err(CustomError::ConfigError("could not render the page".to_string()))
+        }
+    };
 
-            // //////////////////////////
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
 
-            println!("sanitized_search_strings: {:#?}", sanitized_search_strings);
+// GET /audit : the audit log in the DataTables UI , for users with the AUDIT.ROLE role
+async fn audit_page(req: HttpRequest, tera: web::Data<Tera>, audit: web::Data<Audit>) -> Result<HttpResponse, CustomError> {
+    let user = get_current_user(&req);
+    check_audit_access(&audit, user.as_ref())?;
 
-            println!("@ length of sanitized_search_strings >> {}", sanitized_search_strings.len());
+    let mut context = Context::new();
+    context.insert("title", "Audit Log");
+    context.insert("message", "Audit Log");
+    context.insert("tables", &[serde_json::json!({
+        "short_name": "audit_log",
+        "title": "Audit Log",
+        "columns": AUDIT_COLUMNS,
+        "permissions": [],
+    })]);
+    context.insert("username", &user.map(|u| u.username));
+    context.insert("query_url", "/audit/query");
+    context.insert("default_order", "desc");
+
+    let rendered = match tera.render("index.html", &context) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("could not render index.html : {:?}", e);
+            return Err(CustomError::ConfigError("could not render the page".to_string()))
         }
+    };
 
-        if key == "exactsearch" {
-            exact_search = value.to_string();
-        }
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
 
-        if key == "tablename" {
-            table_short_name = value.to_string();
+// POST /audit/query : same protocol as /query
+async fn audit_query(req: HttpRequest, form: web::Form<FormData>, audit: web::Data<Audit>) -> impl Responder {
+    let result = async {
+        let request = parse_datatables_request(&form).await?;
+        check_audit_access(&audit, get_current_user(&req).as_ref())?;
+        let page = get_audit_page(&audit, &get_audit_page_request(&request)?).await?;
+        Ok::<DataTablesResponse, CustomError>(DataTablesResponse {
+            draw: request.draw,
+            records_total: page.records_total,
+            records_filtered: page.records_filtered,
+            data: page.rows,
+            next_cursor: None,
+            prev_cursor: None,
+            scores: None,
+            error: None,
+            request_id: None,
+        })
+    }.await;
+
+    match result {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            let draw = form.fields.get("draw").and_then(|d| d.trim().parse::<u32>().ok()).unwrap_or(0);
+            get_datatables_error_response(draw, &e)
         }
     }
+}
 
-    let table_column_mapping = get_table_column_mapping(table_short_name.as_str()).await;
-    let column_name_to_sort = table_column_mapping.get(sort_column_index.as_str()).unwrap().to_string();
-
-    let mut actual_db_table = get_backend_table(table_short_name.as_str()).await;
-    println!("actual_db_table: [ {} ]", actual_db_table.to_string());
-
-    let mut default_query = "".to_string();
-
-    let mut table_columns = vec![];
-
-    // async fn get_backend_table_columns(table_short_name: &str) -> Vec<String>
-
-    table_columns = get_backend_table_columns(table_short_name.as_str()).await;
-
-    // table_columns.push("random_num".to_string());
-    // table_columns.push("random_float".to_string());
-    // table_columns.push("md5".to_string());
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
 
-    let mut pattern_match = "".to_string();
-    if exact_search == "true" {
-        pattern_match = "exact".to_string();
-    } else if exact_search == "false" {
-        pattern_match = "like".to_string();
-    } else {
-        return HttpResponse::BadRequest().finish()
+    // ./rust-datatable hash-password < password.txt >> argon2 hash for the 'password_hash' of app_users
+    if env::args().nth(1).as_deref() == Some("hash-password") {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        match hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("error : {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(())
     }
 
-    println!("pattern_match: [ {} ]", pattern_match.to_string());
-
-    let mut inner_query = "".to_string();
-
-    let mut total_count_query = "".to_string();
-
-    if search_string_for_reference.is_empty() {
-        default_query = format!("SELECT * FROM {} ORDER BY {} {} LIMIT {} OFFSET {}", actual_db_table, column_name_to_sort, sort_column_order, length, start);
-        println!("default_query (1) : [ {} ]", default_query.to_string());
-        total_count_query = format!("SELECT count(*) FROM {}", actual_db_table);
+    let path = Path::new("/etc/app.rust.env");
+    if path.exists() {
+        dotenv::from_filename("/etc/app.rust.env").ok();
     } else {
-        println!("table_columns : {:#?} , sanitized_search_strings : {:#?} , pattern_match : {} , search_type : {}", table_columns, sanitized_search_strings, pattern_match, search_type);
-        inner_query = get_inner_query(table_columns, sanitized_search_strings, pattern_match, search_type).await.unwrap();
-        println!("inner_query (2) : {}", inner_query.to_string());
-
-        default_query = format!("SELECT * FROM {} WHERE {} ORDER BY {} {} LIMIT {} OFFSET {}", actual_db_table, inner_query, column_name_to_sort, sort_column_order, length, start);
-        println!("default_query (2) : {}", default_query.to_string());
-        total_count_query = format!("SELECT count(*) FROM {} WHERE {}", actual_db_table, inner_query);
+        dotenv().ok();
+        dotenv::from_filename("app.rust.env").ok();
     }
 
-    let my_db_pool = get_db_pool_for_table(table_short_name.as_str()).await.unwrap();
+    // LOG.LEVEL , LOG.FORMAT (see log_ops)
+    let log_config = match load_log_config().and_then(|c| init_logging(&c).map(|_| c)) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("error : {}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("logging : level ({}) , format ({:?})", log_config.level, log_config.format);
 
-    let client: deadpool_postgres::Client = my_db_pool.get().await.unwrap();
+    let tera = match Tera::new("templates/**/*") {
+        Ok(t) => t,
+        Err(e) => {
+            error!("could not load templates : {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let rows = client.query(default_query.as_str(), &[]).await.map_err(|_| HttpResponse::BadRequest().finish()).unwrap();
+    // table registry (tables.yaml) : which tables are served and how
+    let registry_file = env::var("TABLES.CONFIG").unwrap_or_else(|_| "tables.yaml".to_string());
 
-    let mut data_rows_1:Vec<Data1> = Vec::new();
-    let mut data_rows_2:Vec<Data2> = Vec::new();
+    let mut registry = match load_table_registry(registry_file.as_str()) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("could not load table registry : {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    match table_short_name.as_str() {
-        "table1" => {
-            data_rows_1 = fetch(&client, default_query.as_str()).await.unwrap();
-        },
-        "table2" => {
-            data_rows_2 = fetch(&client, default_query.as_str()).await.unwrap();
-        },
-        _ => {
-            // default is 'table1'
-            data_rows_1 = fetch(&client, default_query.as_str()).await.unwrap();
-        },
+    // postgres pools are created once and shared by all the workers
+    let mut pools = match make_db_pools(&registry).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("could not create db pools : {}", e);
+            std::process::exit(1);
+        }
     };
 
-    let records_total = get_count_of_records(total_count_query, my_db_pool).await.unwrap();
-    println!("records_total : {}", records_total);
-    let records_filtered = records_total;
-
-    match table_short_name.as_str() {
-        "table1" => {
-            let response = json!({
-                "data": data_rows_1,
-                "draw": draw,
-                "recordsFiltered": records_filtered,
-                "recordsTotal": records_total,
-            });
-            HttpResponse::Ok().json(response)
-        },
-        "table2" => {
-            let response = json!({
-                "data": data_rows_2,
-                "draw": draw,
-                "recordsFiltered": records_filtered,
-                "recordsTotal": records_total,
-            });
-            HttpResponse::Ok().json(response)
-        },
-        _ => {
-            // default
-            let response = json!({
-                "data": data_rows_1,
-                "draw": draw,
-                "recordsFiltered": records_filtered,
-                "recordsTotal": records_total,
-            });
-            HttpResponse::Ok().json(response)
-        },
+    if let Err(e) = validate_table_registry(&mut registry, &pools).await {
+        error!("table registry does not match the database : {}", e);
+        std::process::exit(1);
     }
-}
 
-async fn index(tera: web::Data<Tera>) -> impl Responder {
-    let mut context = Context::new();
-    context.insert("title", "Data Table");
-    context.insert("message", "PS Table");
+    let proxy = match load_proxy_config() {
+        Ok(p) => web::Data::new(p),
+        Err(e) => {
+            error!("invalid proxy config : {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let mut rendered = "".to_string();
+    // local users (AUTH.MODE) : the users and API tokens tables are checked at startup
+    let auth = match load_auth_config(&proxy) {
+        Ok(config) => match make_auth(config, &mut pools).await {
+            Ok(a) => web::Data::new(a),
+            Err(e) => {
+                error!("could not set up authentication : {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(e) => {
+            error!("invalid auth config : {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    rendered = tera.render("index.html", &context).unwrap();
+    check_access_rules(&registry, &auth);
 
-    HttpResponse::Ok().content_type("text/html").body(rendered)
-}
+    // the server only speaks plain http , browsers never send a Secure cookie back over it
+    if auth.config.enabled && auth.config.cookie_secure && !proxy.trust_forwarded {
+        warn!("AUTH.COOKIE_SECURE is true but the server listens with plain http on ({}) : logins fail unless an https reverse proxy is in front (PROXY.TRUST_FORWARDED=true) , set AUTH.COOKIE_SECURE=false to serve plain http", BIND_ADDRESS);
+    }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+    // audit log of the searches and exports (AUDIT.MODE)
+    let audit = match load_audit_config() {
+        Ok(config) => match make_audit(config, &mut pools).await {
+            Ok(a) => web::Data::new(a),
+            Err(e) => {
+                error!("could not set up the audit log : {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(e) => {
+            error!("invalid audit config : {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let path = Path::new("/etc/app.rust.env");
-    if path.exists() {
-        dotenv::from_filename("/etc/app.rust.env").ok();
-    } else {
-        dotenv().ok();
-        dotenv::from_filename("app.rust.env").ok();
-    }
+    let cors_origins = match load_cors_origins() {
+        Ok(o) => o,
+        Err(e) => {
+            error!("invalid cors config : {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    std::env::set_var("RUST_LOG", "debug");
-    env_logger::init();
+    let registry = web::Data::new(registry);
+    let pools = web::Data::new(pools);
+    let export_jobs = web::Data::new(ExportJobs::default());
 
-    let tera = Tera::new("templates/**/*").unwrap();
+    // exports are deleted once older than EXPORT.TTL_SECS (or when data_dir is above EXPORT.MAX_TOTAL_MB)
+    let retention = match load_export_retention() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("invalid export retention config : {}", e);
+            std::process::exit(1);
+        }
+    };
+    start_export_sweeper(retention.clone(), export_jobs.clone());
+    let retention = web::Data::new(retention);
 
-    // let my_db_pool = get_db_pool_for_table("table1").await.unwrap();
+    // exports are downloaded through signed links valid for DOWNLOAD.LINK_TTL_SECS
+    let download = match load_download_config() {
+        Ok(d) => web::Data::new(d),
+        Err(e) => {
+            error!("invalid download config : {}", e);
+            std::process::exit(1);
+        }
+    };
 
     HttpServer::new(move || {
-        let cors = Cors::permissive();
+        let cors = make_cors(&cors_origins);
 
         App::new()
             .app_data(actix_web::web::Data::new(tera.clone()))
+            .app_data(registry.clone())
+            .app_data(pools.clone())
+            .app_data(export_jobs.clone())
+            .app_data(retention.clone())
+            .app_data(download.clone())
+            .app_data(auth.clone())
+            .app_data(audit.clone())
+            .app_data(proxy.clone())
             .app_data(web::JsonConfig::default().error_handler(handle_deserialize_error))
+            .app_data(web::FormConfig::default().error_handler(handle_form_error))
+            .app_data(web::QueryConfig::default().error_handler(handle_query_error))
+            .wrap(from_fn(with_auth))
             .wrap(cors)
+            .wrap_fn(with_request_id)
             .service(query_data)
             .route("/tables", web::get().to(index))
-            .service(Files::new("/data_dir", "./data_dir").show_files_listing())
+            .route("/audit", web::get().to(audit_page))
+            .route("/audit/query", web::post().to(audit_query))
+            .route("/login", web::get().to(login_page))
+            .route("/login", web::post().to(login_submit))
+            .route("/logout", web::post().to(logout_submit))
+            .route("/auth/tokens", web::post().to(create_token))
+            .route("/downloads/{file_name}", web::get().to(download_export))
             .route("/export_csv", web::post().to(handle_post))
+            .route("/exports/{id}", web::get().to(get_export_status))
+            .route("/exports/{id}", web::delete().to(cancel_export))
+            .route("/admin/exports", web::get().to(admin_list_exports))
+            .route("/admin/exports", web::delete().to(admin_purge_exports))
+            .route("/admin/exports/{file_name}", web::delete().to(admin_delete_export))
+            .route("/api/v1/tables", web::get().to(api_list_tables))
+            .route("/api/v1/tables/{name}/schema", web::get().to(api_table_schema))
+            .route("/api/v1/tables/{name}/rows", web::post().to(api_table_rows))
+            .route("/metrics", web::get().to(metrics))
+            .route("/healthz", web::get().to(healthz))
+            .route("/readyz", web::get().to(readyz))
     })
-    .bind("0.0.0.0:5050")?
+    .bind(BIND_ADDRESS)?
     .run()
     .await
 }
@@ -329,147 +405,267 @@ async fn main() -> std::io::Result<()> {
 
 /* ************************************************************************************* */
 
-async fn get_valid_search_strings(input_search_string: &str) -> Result<SearchStringData,CustomErrorType> {
-    let mut search_string_for_reference = "".to_string();
-    let mut search_strings:Vec<String> = vec![];
-    let search_string = input_search_string.to_string();
-    let mut search_type = "or".to_string();
-    search_string_for_reference = input_search_string.to_string();
-    let mut sanitized_search_strings: Vec<String> = vec![];
-
-    let mut search_strings_with_leading_and_trailing_spaces_removed = vec![];
-
-    println!("search_string_for_reference: {}", search_string_for_reference);
-
-    if !search_string.is_empty() {
-        // you cannot have both AND ('+') and OR ('|') based search
-        // it should be either '+' or '|'
-        if search_string.contains("+") && search_string.contains("|") {
-            let msg = "error : cannot search, search_string contains both '+' (AND search) and '|' (OR search) !".to_string();
-            println!("{}", msg.to_string());
-            let custom_err = CustomErrorType {
-                err_type: GenericError::InvalidInput,
-                err_msg: msg.to_string(),
-            };
+fn handle_deserialize_error(err: actix_web::error::JsonPayloadError, _: &actix_web::HttpRequest) -> actix_web::Error {
+    error!("invalid JSON payload : {}", err);
+    if let error::JsonPayloadError::Deserialize(ref serde_err) = err {
+        if serde_err.classify() == Category::Data {
+            // Handle missing field or other data-related errors
+            return CustomError::ValidationError("invalid request, please check payload".to_string()).into();
         }
     }
 
-    if search_string.contains("+") { // AND search
-        search_strings = split_string(search_string.as_str(), "+").await;
-        search_type = "and".to_string();
-    } else if search_string.contains("|") { // OR search
-        search_strings = split_string(search_string.as_str(), "|").await;
-        search_type = "or".to_string();
-    } else { // default search, which means (search_string) does not contain either '+' or '|'
-        search_type = "or".to_string();
-        search_strings = Vec::from([search_string]);
-    }
+    // Fallback: handle other kinds of errors
+    CustomError::ValidationError("invalid request".to_string()).into()
+}
+
+fn handle_form_error(err: actix_web::error::UrlencodedError, _: &actix_web::HttpRequest) -> actix_web::Error {
+    error!("invalid form payload : {}", err);
+    CustomError::ValidationError("invalid request, please check form fields".to_string()).into()
+}
+
+fn handle_query_error(err: actix_web::error::QueryPayloadError, _: &actix_web::HttpRequest) -> actix_web::Error {
+    error!("invalid query string : {}", err);
+    CustomError::ValidationError("invalid request, please check query parameters".to_string()).into()
+}
 
-    // below sanitize_string(...) will ensure to remove
-    // all characters except these : "_./-@,#:;"
-    // so that way, DB does not get any characters that are not needed
-    // let mut search_strings_with_leading_and_trailing_spaces_removed: Vec<String> = search_strings.iter().map(|s| remove_leading_and_trailing_spaces(s)).collect();
+/* ************************************************************************************* */
 
-    let futures_1: Vec<_> = search_strings.iter().map(|s| async {
-        remove_leading_and_trailing_spaces(s).await
-    }).collect();
-    search_strings_with_leading_and_trailing_spaces_removed = futures::future::join_all(futures_1).await;
 
-    println!("@ length of search_strings_with_leading_and_trailing_spaces_removed >> {}", search_strings_with_leading_and_trailing_spaces_removed.len());
+async fn handle_post(req: HttpRequest, item: web::Json<ExportData>, registry: web::Data<TableRegistry>, pools: web::Data<DbPools>, jobs: web::Data<ExportJobs>, retention: web::Data<ExportRetention>, download: web::Data<DownloadConfig>) -> Result<HttpResponse, CustomError> {
+    info!(table = %item.table_name, pattern_match = %item.pattern_match, format = ?item.format, "export requested");
 
-    // //////////////////////////
+    // the audit entry is written when the export ends (see export_ops) , or now when it cannot start
+    let mut event = start_audit_event(&req, "export");
+    event.entry.table_name = item.table_name.to_string();
+    event.entry.search = get_export_search_description(&item);
+    event.entry.pattern_match = item.pattern_match.to_string();
 
-    // sanitized_search_strings = search_strings_with_leading_and_trailing_spaces_removed.iter().map(|s| sanitize_string(s)).collect();
+    let (table, where_clause, my_db_pool) = match prepare_export(&req, &item, &registry, &pools, &retention).await {
+        Ok(p) => p,
+        Err(e) => {
+            finish_audit_event(event, None, e.code());
+            return Err(e)
+        }
+    };
 
-    let futures_2: Vec<_> = search_strings_with_leading_and_trailing_spaces_removed.iter().map(|s| async {
-        sanitize_string(s).await
-    }).collect();
-    sanitized_search_strings = futures::future::join_all(futures_2).await;
+    let status = start_export_job(jobs, my_db_pool, table, where_clause, item.format, &download, event)?;
 
-    println!("@ length of sanitized_search_strings >> {}", sanitized_search_strings.len());
+    Ok(HttpResponse::Accepted().json(status))
+}
 
-    // //////////////////////////
+// the table (as the user may export it) , the where clause of the search and the pool of the table
+async fn prepare_export(req: &HttpRequest, item: &ExportData, registry: &TableRegistry, pools: &DbPools, retention: &ExportRetention) -> Result<(TableConfig, SqlQuery, Pool), CustomError> {
+    let user = get_current_user(req);
+    let table = get_table_view_for_user(registry, item.table_name.as_str(), user.as_ref(), Permission::Export)?;
+    set_request_table(req, table.short_name.as_str());
 
-    println!("sanitized_search_strings: {:#?}", sanitized_search_strings);
+    check_fuzzy_search(&table, item.pattern_match.as_str())?;
 
-    println!("@ length of sanitized_search_strings >> {}", sanitized_search_strings.len());
+    // '___' is sent from the UI : which tells the backend to export all the rows of the table
+    let search = if item.search_string.trim() == "___" {
+        None
+    } else {
+        parse_search_expression(item.search_string.as_str(), &table.columns, item.pattern_match.as_str())?
+    };
 
-    let result = SearchStringData {
-        search_string: sanitized_search_strings,
-        search_type,
+    let search_filters = SearchFilters {
+        search,
+        pattern_match: item.pattern_match.to_string(),
+        columns: table.column_info.clone(),
+        fulltext: get_fulltext_config(&table),
+        searchable_columns: table.columns.clone(),
+        column_searches: get_export_column_searches(&table, item)?,
     };
 
-    Ok(result)
+    let where_clause = get_where_clause(&search_filters).await?;
+
+    let my_db_pool = get_db_pool_for_table(pools, &table).await?;
+
+    // the export runs in the background , the response only carries the job id
+    check_export_quota(retention).await?;
+
+    Ok((table, where_clause, my_db_pool))
 }
 
-/* ************************************************************************************* */
+// the per-column filters shown in the UI , masked columns are not searched (as in /query)
+fn get_export_column_searches(table: &TableConfig, item: &ExportData) -> Result<Vec<ColumnSearch>, CustomError> {
+    let mut column_searches = vec![];
+    for column_search in &item.column_searches {
+        let column = match table.column_info.iter().find(|c| c.name == column_search.column_name) {
+            Some(c) => c,
+            None => return Err(CustomError::ValidationError(format!("invalid column ({})", column_search.column_name)))
+        };
+        if column.masked {
+            continue
+        }
+        if let Some(search) = parse_search_expression(column_search.search_string.as_str(), &table.columns, item.pattern_match.as_str())? {
+            column_searches.push(ColumnSearch {
+                column_name: column.name.to_string(),
+                search,
+                pattern_match: item.pattern_match.to_string(),
+            });
+        }
+    }
+    Ok(column_searches)
+}
 
-fn handle_deserialize_error(err: actix_web::error::JsonPayloadError, _: &actix_web::HttpRequest) -> actix_web::Error {
-    let detail = err.to_string();
-    if let error::JsonPayloadError::Deserialize(ref serde_err) = err {
-        match serde_err.classify() {
-            Category::Data => {
-                // Handle missing field or other data-related errors
-                return error::InternalError::from_response(
-                    "",
-                    HttpResponse::BadRequest().content_type("application/json").body("{\"response\":\"invalid request, please check payload\"}")
-                ).into();
-            },
-            _ => (),
+// as recorded in the audit log , same format as the searches of /query >> 'xyz ; md5: abc'
+fn get_export_search_description(item: &ExportData) -> String {
+    let mut searches = vec![item.search_string.trim().to_string()];
+    for column_search in &item.column_searches {
+        if !column_search.search_string.trim().is_empty() {
+            searches.push(format!("{}: {}", column_search.column_name, column_search.search_string.trim()));
         }
     }
+    searches.join(" ; ")
+}
 
-    // Fallback: handle other kinds of errors
-    error::InternalError::from_response(
-        err,
-        // HttpResponse::InternalServerError().json("Internal server error")
-        HttpResponse::InternalServerError().content_type("application/json").body("{\"response\":\"invalid request\"}")
-    ).into()
+async fn get_export_status(req: HttpRequest, path: web::Path<String>, jobs: web::Data<ExportJobs>, download: web::Data<DownloadConfig>) -> Result<web::Json<JsonResponseWithCSVExportData>, CustomError> {
+    Ok(web::Json(get_export_job(&jobs, path.as_str(), &download, get_client_identity(&req).as_str())?))
+}
+
+async fn cancel_export(req: HttpRequest, path: web::Path<String>, jobs: web::Data<ExportJobs>, download: web::Data<DownloadConfig>) -> Result<web::Json<JsonResponseWithCSVExportData>, CustomError> {
+    Ok(web::Json(cancel_export_job(&jobs, path.as_str(), &download, get_client_identity(&req).as_str()).await?))
+}
+
+// GET /downloads/{file_name}?expires=...&sig=... >> the link from the status of a completed export
+async fn download_export(req: HttpRequest, path: web::Path<String>, params: web::Query<DownloadParams>, download: web::Data<DownloadConfig>) -> Result<HttpResponse, CustomError> {
+    get_download_response(&req, &download, path.as_str(), &params).await
 }
 
 /* ************************************************************************************* */
 
+// GET /admin/exports >> every export in data_dir with its metadata and expiry
+// (only the exports of the tables the user is an admin of , see access_ops)
+async fn admin_list_exports(req: HttpRequest, retention: web::Data<ExportRetention>, registry: web::Data<TableRegistry>) -> Result<web::Json<ExportListResponse>, CustomError> {
+    let user = get_current_user(&req);
+    Ok(web::Json(get_export_list(&retention, |t| can_admin_exports(&registry, retention.admin_role.as_str(), user.as_ref(), t)).await?))
+}
+
+// DELETE /admin/exports?older_than_secs=3600&table_name=table1 >> both filters are optional
+async fn admin_purge_exports(req: HttpRequest, params: web::Query<ExportPurgeParams>, retention: web::Data<ExportRetention>, registry: web::Data<TableRegistry>) -> Result<web::Json<ExportPurgeResponse>, CustomError> {
+    let user = get_current_user(&req);
+    Ok(web::Json(purge_exports(&params, |t| can_admin_exports(&registry, retention.admin_role.as_str(), user.as_ref(), t)).await?))
+}
+
+async fn admin_delete_export(req: HttpRequest, path: web::Path<String>, retention: web::Data<ExportRetention>, registry: web::Data<TableRegistry>) -> Result<web::Json<ExportPurgeResponse>, CustomError> {
+    let metadata = get_export_metadata(path.as_str()).await?;
+    if !can_admin_exports(&registry, retention.admin_role.as_str(), get_current_user(&req).as_ref(), metadata.table_name.as_str()) {
+        return Err(CustomError::Forbidden(format!("no admin permission on table ({})", metadata.table_name)))
+    }
+    let freed_bytes = delete_export(path.as_str()).await?;
+    Ok(web::Json(ExportPurgeResponse {
+        deleted: vec![path.to_string()],
+        failed: vec![],
+        freed_bytes,
+    }))
+}
 
-async fn handle_post(item: web::Json<ExportData>) -> impl Responder {
-    println!("Received : [ search_string : ({}) ] , [ table_name : ({}) ], [ pattern_match : ({}) ]", item.search_string, item.table_name, item.pattern_match);
+/* ************************************************************************************* */
 
-    let search_type = get_valid_search_strings(item.search_string.as_str()).await.unwrap().search_type;
-    println!("search_type : {}", search_type);
+// GET /api/v1/tables
+async fn api_list_tables(req: HttpRequest, registry: web::Data<TableRegistry>) -> web::Json<ApiTablesResponse> {
+    web::Json(get_api_tables(&get_visible_tables(&registry, get_current_user(&req).as_ref())))
+}
 
-    let search_strings = get_valid_search_strings(item.search_string.as_str()).await.unwrap().search_string;
-    println!("search_strings : {:#?}", search_strings);
+// GET /api/v1/tables/{name}/schema
+async fn api_table_schema(req: HttpRequest, path: web::Path<String>, registry: web::Data<TableRegistry>) -> Result<web::Json<ApiTableSchema>, CustomError> {
+    let table = &get_table_view_for_user(&registry, path.as_str(), get_current_user(&req).as_ref(), Permission::View)?;
+    set_request_table(&req, table.short_name.as_str());
+    Ok(web::Json(get_api_table_schema(table)))
+}
 
-    let backend_table = get_backend_table(item.table_name.as_str()).await;
-    println!("backend_table : {}", backend_table);
+// GET /healthz : the process is alive , nothing else is checked
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+}
 
-    let columns = get_backend_table_columns(item.table_name.as_str()).await;
-    println!("columns : {:#?}", columns);
+// GET /readyz : 503 while a db pool , data_dir or the templates are not usable (see health_ops)
+async fn readyz(pools: web::Data<DbPools>, tera: web::Data<Tera>) -> HttpResponse {
+    let readiness = get_readiness(&pools, &tera).await;
+    if readiness.status == "ready" {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
 
-    let my_db_pool = get_db_pool_for_table(item.table_name.as_str()).await.unwrap();
-    
-    let my_table = item.table_name.as_str();
+// GET /metrics : Prometheus (see metrics_ops)
+async fn metrics(pools: web::Data<DbPools>) -> Result<HttpResponse, CustomError> {
+    let text = get_metrics(&pools).await?;
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(text))
+}
 
-    let result = match export_table_to_csv(my_db_pool, my_table, columns, search_strings, item.pattern_match.to_string(), search_type).await {
-        Ok(d) => {
-            let response_data = JsonResponseWithCSVExportData {
-                message: d.csv_file_path,
-                status: 200,
-                rows: d.rows,
-                time_taken_for_export: d.time_taken_for_export,
-            };
-            web::Json(response_data)
+// POST /api/v1/tables/{name}/rows
+async fn api_table_rows(req: HttpRequest, path: web::Path<String>, item: web::Json<ApiRowsRequest>, registry: web::Data<TableRegistry>, pools: web::Data<DbPools>) -> Result<HttpResponse, CustomError> {
+    let mut event = start_audit_event(&req, "api_rows");
+    event.entry.table_name = path.to_string();
+    event.entry.search = get_api_search_description(&item);
+    event.entry.pattern_match = item.pattern_match.to_string();
+
+    let result = async {
+        let table = &get_table_view_for_user(&registry, path.as_str(), get_current_user(&req).as_ref(), Permission::View)?;
+        set_request_table(&req, table.short_name.as_str());
+        let my_db_pool = get_db_pool_for_table(&pools, table).await?;
+        get_api_rows(table, &my_db_pool, &item).await
+    }.await;
+
+    match result {
+        Ok(response) => {
+            finish_audit_event(event, Some(response.rows.len() as i64), "ok");
+            Ok(get_json_response(&response))
         },
         Err(e) => {
-            println!("error : could not export CSV file : {:#?}", e);
-            let response_data = JsonResponseWithCSVExportData {
-                message: "error : could not export CSV file".to_string(),
-                status: 400,
-                rows: 0,
-                time_taken_for_export: 0.0,
-            };
-            web::Json(response_data)
+            finish_audit_event(event, None, e.code());
+            Err(e)
+        }
+    }
+}
+
+/* ************************************************************************************* */
+
+fn render_login_page(tera: &Tera, next: &str, error: Option<&str>) -> Result<String, CustomError> {
+    let mut context = Context::new();
+    context.insert("title", "Data Table");
+    context.insert("next", &get_safe_next(next));
+    context.insert("error", &error);
+    match tera.render("login.html", &context) {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("could not render login.html : {:?}", e);
+            Err(CustomError::ConfigError("could not render the page".to_string()))
+        }
+    }
+}
+
+// GET /login?next=/tables
+async fn login_page(params: web::Query<LoginParams>, tera: web::Data<Tera>) -> Result<HttpResponse, CustomError> {
+    Ok(HttpResponse::Ok().content_type("text/html").body(render_login_page(&tera, params.next.as_str(), None)?))
+}
+
+// POST /login (form) >> session cookie and back to 'next' , the form again with the error otherwise
+async fn login_submit(form: web::Form<LoginForm>, tera: web::Data<Tera>, auth: web::Data<Auth>) -> Result<HttpResponse, CustomError> {
+    match login(&auth, form.username.as_str(), form.password.as_str()).await {
+        Ok(cookie) => Ok(HttpResponse::SeeOther()
+            .cookie(cookie)
+            .insert_header((header::LOCATION, get_safe_next(form.next.as_str())))
+            .finish()),
+        Err(CustomError::Unauthorized(msg)) => {
+            Ok(HttpResponse::Unauthorized().content_type("text/html").body(render_login_page(&tera, form.next.as_str(), Some(msg.as_str()))?))
         },
-    };
+        Err(e) => Err(e),
+    }
+}
 
-    web::Json(result)
-}
\ No newline at end of file
+// POST /logout
+async fn logout_submit(req: HttpRequest, auth: web::Data<Auth>) -> HttpResponse {
+    logout(&auth, &req);
+    let mut response = HttpResponse::SeeOther().insert_header((header::LOCATION, "/login")).finish();
+    let _ = response.add_removal_cookie(&actix_web::cookie::Cookie::build(SESSION_COOKIE, "").path("/").finish());
+    response
+}
+
+// POST /auth/tokens >> API token of the calling user , for 'Authorization: Bearer <token>'
+async fn create_token(req: HttpRequest, item: web::Json<ApiTokenRequest>, auth: web::Data<Auth>) -> Result<web::Json<ApiTokenResponse>, CustomError> {
+    Ok(web::Json(create_api_token(&auth, get_current_user(&req), &item).await?))
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+use lazy_static::lazy_static;
+use prometheus::{exponential_buckets, register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec};
+use prometheus::{Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder};
+use tracing::error;
+use crate::data_types::{CustomError, DbPools};
+use crate::retention_ops::get_export_dir_usage;
+
+// Prometheus metrics , GET /metrics (text format)
+//   datatable_http_requests_total , datatable_http_request_duration_seconds : per route (pattern) , method , table , status
+//   datatable_query_duration_seconds : data query and count queries of a page (/query and /api/v1) , per table
+//   datatable_db_pool_connections (in_use , idle) , datatable_db_pool_waiting : per pool , read at scrape time
+//   datatable_exports_total , datatable_export_duration_seconds , datatable_export_rows , datatable_export_size_bytes : per table and format
+//   datatable_data_dir_bytes , datatable_data_dir_files : the files of data_dir , read at scrape time
+//
+// 'table' is only set once the table is known to the registry (see set_request_table) , '-' otherwise
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "datatable_http_requests_total", "HTTP requests", &["route", "method", "table", "status"]).unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "datatable_http_request_duration_seconds", "HTTP request latency", &["route", "method", "table"]).unwrap();
+    static ref QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "datatable_query_duration_seconds", "duration of the queries of a page : 'data' , 'total_count' , 'filtered_count'", &["table", "query"]).unwrap();
+    static ref DB_POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "datatable_db_pool_connections", "connections of the db pool : 'in_use' , 'idle'", &["pool", "state"]).unwrap();
+    static ref DB_POOL_MAX_SIZE: IntGaugeVec = register_int_gauge_vec!(
+        "datatable_db_pool_max_size", "max connections of the db pool", &["pool"]).unwrap();
+    static ref DB_POOL_WAITING: IntGaugeVec = register_int_gauge_vec!(
+        "datatable_db_pool_waiting", "requests waiting for a connection of the db pool", &["pool"]).unwrap();
+    static ref EXPORTS: IntCounterVec = register_int_counter_vec!(
+        "datatable_exports_total", "exports by status : 'ok' , 'cancelled' or the error code", &["table", "format", "status"]).unwrap();
+    static ref EXPORT_DURATION: HistogramVec = register_histogram_vec!(
+        "datatable_export_duration_seconds", "duration of the exports", &["table", "format"],
+        exponential_buckets(0.1, 4.0, 8).unwrap()).unwrap();
+    static ref EXPORT_ROWS: HistogramVec = register_histogram_vec!(
+        "datatable_export_rows", "rows of the completed exports", &["table", "format"],
+        exponential_buckets(10.0, 10.0, 7).unwrap()).unwrap();
+    static ref EXPORT_SIZE: HistogramVec = register_histogram_vec!(
+        "datatable_export_size_bytes", "size of the completed exports", &["table", "format"],
+        exponential_buckets(1024.0, 8.0, 8).unwrap()).unwrap();
+    static ref DATA_DIR_BYTES: IntGauge = register_int_gauge!(
+        "datatable_data_dir_bytes", "bytes used by the files of data_dir").unwrap();
+    static ref DATA_DIR_FILES: IntGauge = register_int_gauge!(
+        "datatable_data_dir_files", "files in data_dir (exports , partial exports , metadata)").unwrap();
+}
+
+pub fn observe_request(route: &str, method: &str, table: &str, status: u16, duration: Duration) {
+    HTTP_REQUESTS.with_label_values(&[route, method, table, status.to_string().as_str()]).inc();
+    HTTP_REQUEST_DURATION.with_label_values(&[route, method, table]).observe(duration.as_secs_f64());
+}
+
+pub fn observe_query(table: &str, query: &str, duration: Duration) {
+    QUERY_DURATION.with_label_values(&[table, query]).observe(duration.as_secs_f64());
+}
+
+// rows and size are only observed for completed exports
+pub fn observe_export(table: &str, format: &str, status: &str, rows: i64, size_bytes: u64, duration: Duration) {
+    EXPORTS.with_label_values(&[table, format, status]).inc();
+    EXPORT_DURATION.with_label_values(&[table, format]).observe(duration.as_secs_f64());
+    if status == "ok" {
+        EXPORT_ROWS.with_label_values(&[table, format]).observe(rows as f64);
+        EXPORT_SIZE.with_label_values(&[table, format]).observe(size_bytes as f64);
+    }
+}
+
+/* ************************************************************************************* */
+
+// the gauges of the pools and of data_dir are read at scrape time
+pub async fn get_metrics(pools: &DbPools) -> Result<String, CustomError> {
+    for (name, pool) in &pools.pools {
+        let status = pool.status();
+        let in_use = status.size.saturating_sub(status.available);
+        DB_POOL_CONNECTIONS.with_label_values(&[name.as_str(), "in_use"]).set(in_use as i64);
+        DB_POOL_CONNECTIONS.with_label_values(&[name.as_str(), "idle"]).set(status.available as i64);
+        DB_POOL_MAX_SIZE.with_label_values(&[name.as_str()]).set(status.max_size as i64);
+        DB_POOL_WAITING.with_label_values(&[name.as_str()]).set(status.waiting as i64);
+    }
+
+    let (files, bytes) = get_export_dir_usage().await?;
+    DATA_DIR_FILES.set(files as i64);
+    DATA_DIR_BYTES.set(bytes as i64);
+
+    let mut buffer = vec![];
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("could not encode the metrics : {}", e);
+        return Err(CustomError::ConfigError("could not encode the metrics".to_string()))
+    }
+    match String::from_utf8(buffer) {
+        Ok(text) => Ok(text),
+        Err(e) => {
+            error!("could not encode the metrics : {}", e);
+            Err(CustomError::ConfigError("could not encode the metrics".to_string()))
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use deadpool_postgres::Pool;
+use serde_json::{Map, Value};
+use tracing::{debug, info};
+use crate::data_types::{CustomError, PageRequest, SearchFilters, SortColumn, TableConfig, TablePage};
+use crate::db_ops::{fetch_as_json, get_count_of_records, get_total_count_of_records};
+use crate::fulltext_ops::get_fulltext_select;
+use crate::fuzzy_ops::get_fuzzy_select;
+use crate::log_ops::{get_redacted_params, timed};
+use crate::metrics_ops::observe_query;
+use crate::keyset_ops::{add_keyset_condition, get_cursor_columns, get_keyset_cursor, get_keyset_order_by, get_keyset_sort, get_page_cursors};
+use crate::query_ops::{build_count_query, build_select_query, get_order_by_clause, get_where_clause, prepend_order_by};
+
+// computed columns of the fulltext and fuzzy searches , never part of the rows (they are not columns of the table)
+const SCORE_COLUMNS: [&str; 3] = ["_rank", "_headline", "_similarity"];
+
+// removes the score columns from the rows , returns them in the order of the rows
+fn take_scores(rows: &mut [Value]) -> Vec<Value> {
+    rows.iter_mut()
+        .map(|row| {
+            let mut scores = Map::new();
+            if let Some(row) = row.as_object_mut() {
+                for name in SCORE_COLUMNS {
+                    if let Some(score) = row.remove(name) {
+                        scores.insert(name.to_string(), score);
+                    }
+                }
+            }
+            Value::Object(scores)
+        })
+        .collect()
+}
+
+// one page of a table with its counts , shared by /query (DataTables) and /api/v1/tables/{name}/rows
+// columns : projection , every column of the table when empty
+pub async fn get_table_page(table: &TableConfig, pool: &Pool, search_filters: &SearchFilters, sort_columns: &[SortColumn], columns: &[String], page: &PageRequest) -> Result<TablePage, CustomError> {
+    let where_clause = get_where_clause(search_filters).await?;
+
+    // fulltext : '_rank' (and '_headline') , fuzzy : '_similarity'
+    // they are bound in a copy of the where clause , the counts do not use them
+    let mut select_clause = where_clause.clone();
+    let mut order_by_clause = get_order_by_clause(sort_columns);
+    let mut extra_columns = vec![];
+    let mut score_order = false;
+    if let Some(columns) = get_fulltext_select(&mut select_clause, search_filters) {
+        if search_filters.fulltext.order_by_rank {
+            order_by_clause = prepend_order_by(order_by_clause.as_str(), "\"_rank\" DESC");
+            score_order = true;
+        }
+        extra_columns = columns;
+    } else if let Some(columns) = get_fuzzy_select(&mut select_clause, search_filters) {
+        order_by_clause = prepend_order_by(order_by_clause.as_str(), "\"_similarity\" DESC");
+        score_order = true;
+        extra_columns = columns;
+    }
+    let has_scores = !extra_columns.is_empty();
+
+    // keyset pagination : a cursor sent by the client replaces the OFFSET
+    let keyset_sort = get_keyset_sort(table, sort_columns, score_order);
+    let mut cursor = None;
+    let mut offset = page.start;
+    if let Some(sort) = &keyset_sort {
+        cursor = get_keyset_cursor(page, sort, &where_clause)?;
+        order_by_clause = get_keyset_order_by(sort, cursor.as_ref());
+        extra_columns.extend(get_cursor_columns(sort));
+        if let Some(c) = &cursor {
+            add_keyset_condition(&mut select_clause, table, sort, c);
+            offset = 0;
+        }
+    }
+
+    let default_query = build_select_query(table, columns, &select_clause, &extra_columns, order_by_clause.as_str(), page.length, offset).await;
+    debug!(sql = %default_query.sql, params = ?get_redacted_params(&default_query.params), "page query");
+
+    // recordsTotal : rows in the table , recordsFiltered : rows matching the search
+    // the data query and both counts run concurrently, each on its own connection
+    let filtered_count_query = build_count_query(table, &where_clause).await;
+    let is_filtered = !where_clause.sql.is_empty();
+
+    let data_future = fetch_as_json(pool, &default_query);
+    let total_count_future = get_total_count_of_records(table, pool);
+    let filtered_count_future = async {
+        if is_filtered {
+            get_count_of_records(&filtered_count_query, pool).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+
+    let ((mut rows, data_query_time), (records_total, total_count_time), (filtered_count, filtered_count_time)) =
+        futures::try_join!(timed(data_future), timed(total_count_future), timed(filtered_count_future))?;
+    let records_filtered = filtered_count.unwrap_or(records_total);
+
+    info!(records_total, records_filtered, rows = rows.len(), data_query_ms = data_query_time.as_millis() as u64,
+          total_count_ms = total_count_time.as_millis() as u64, filtered_count_ms = filtered_count_time.as_millis() as u64, "page fetched");
+    observe_query(table.short_name.as_str(), "data", data_query_time);
+    observe_query(table.short_name.as_str(), "total_count", total_count_time);
+    if is_filtered {
+        observe_query(table.short_name.as_str(), "filtered_count", filtered_count_time);
+    }
+
+    let (next_cursor, prev_cursor) = match &keyset_sort {
+        Some(sort) => get_page_cursors(table, sort, cursor.as_ref(), page, &where_clause, &mut rows, records_filtered),
+        None => (None, None),
+    };
+    let scores = has_scores.then(|| take_scores(&mut rows));
+
+    Ok(TablePage {
+        rows,
+        records_total,
+        records_filtered,
+        next_cursor,
+        prev_cursor,
+        scores,
+    })
+}
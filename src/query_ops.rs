@@ -0,0 +1,163 @@
+use tokio_postgres::types::ToSql;
+use tracing::debug;
+use crate::data_types::{CustomError, DataTablesColumn, DataTablesOrder, SearchFilters, SortColumn, TableConfig};
+use crate::db_ops::{get_backend_table, get_select_columns, get_table_column_mapping};
+use crate::search_ops::get_search_condition;
+use crate::string_ops::quote_identifier;
+
+// a SQL statement (or fragment) with '$1 .. $n' placeholders
+// and the values bound to those placeholders
+#[derive(Debug, Clone, Default)]
+pub struct SqlQuery {
+    pub sql: String,
+    pub params: Vec<String>,
+}
+
+impl SqlQuery {
+    pub fn new() -> SqlQuery {
+        SqlQuery::default()
+    }
+
+    // binds a value and returns the placeholder to use in the SQL >> example : '$3'
+    pub fn bind(&mut self, value: String) -> String {
+        self.params.push(value);
+        format!("${}", self.params.len())
+    }
+
+    pub fn sql_params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|p| p as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+/* ************************************************************************************* */
+
+// only columns declared in the registry and 'asc' / 'desc' can end up in the ORDER BY clause
+// columns DataTables flags as not 'orderable' (and masked columns) are skipped
+pub async fn get_sort_columns(table: &TableConfig, order: &[DataTablesOrder], columns: &[DataTablesColumn]) -> Result<Vec<SortColumn>, CustomError> {
+    let table_column_mapping = get_table_column_mapping(table).await;
+
+    let mut sort_columns = vec![];
+
+    for sort in order {
+        if columns.iter().any(|c| c.index == sort.column && !c.orderable) {
+            debug!("column index ({}) of table ({}) is not orderable, skipped", sort.column, table.short_name);
+            continue
+        }
+
+        let sort_column = match table_column_mapping.get(&sort.column.to_string()) {
+            Some(c) => c.to_string(),
+            None => {
+                return Err(CustomError::ValidationError(format!("invalid sort column index ({})", sort.column)))
+            }
+        };
+
+        // the order of the rows would give the masked values away
+        if table.column_info.iter().any(|c| c.name == sort_column && c.masked) {
+            debug!("column ({}) of table ({}) is masked, not orderable, skipped", sort_column, table.short_name);
+            continue
+        }
+
+        let descending = match sort.dir.to_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            _ => {
+                return Err(CustomError::ValidationError(format!("invalid sort direction ({})", sort.dir)))
+            }
+        };
+
+        sort_columns.push(SortColumn { name: sort_column, descending });
+    }
+
+    Ok(sort_columns)
+}
+
+pub fn get_order_by_clause(sort_columns: &[SortColumn]) -> String {
+    if sort_columns.is_empty() {
+        return "".to_string()
+    }
+
+    let sort_columns: Vec<String> = sort_columns.iter()
+        .map(|s| format!("{} {}", quote_identifier(s.name.as_str()), if s.descending { "DESC" } else { "ASC" }))
+        .collect();
+
+    format!("ORDER BY {}", sort_columns.join(", "))
+}
+
+/* ************************************************************************************* */
+
+// global search and per-column searches are AND-ed together
+// an empty SqlQuery is returned when there is nothing to filter on
+pub async fn get_where_clause(filters: &SearchFilters) -> Result<SqlQuery, CustomError> {
+    let mut where_clause = SqlQuery::new();
+    let mut conditions = vec![];
+
+    if let Some(expr) = &filters.search {
+        conditions.push(get_search_condition(&mut where_clause, expr, filters, &filters.searchable_columns, get_valid_pattern_match(filters.pattern_match.as_str())?)?);
+    }
+
+    // terms without 'column:' only look into the column of the search box
+    for column_search in &filters.column_searches {
+        let default_columns = vec![column_search.column_name.to_string()];
+        conditions.push(get_search_condition(&mut where_clause, &column_search.search, filters, &default_columns, get_valid_pattern_match(column_search.pattern_match.as_str())?)?);
+    }
+
+    if !conditions.is_empty() {
+        where_clause.sql = format!("WHERE {}", conditions.join(" AND "));
+    }
+
+    Ok(where_clause)
+}
+
+fn get_valid_pattern_match(pattern_match: &str) -> Result<&str, CustomError> {
+    match pattern_match {
+        "like" | "exact" | "fulltext" | "fuzzy" | "regex" => Ok(pattern_match),
+        _ => Err(CustomError::ValidationError(format!("invalid pattern_match ({}) , valid values : 'like' , 'exact' , 'fulltext' , 'fuzzy' , 'regex'", pattern_match)))
+    }
+}
+
+// the best matches of a search (example : '"_rank" DESC') come before the order chosen in the UI
+pub fn prepend_order_by(order_by_clause: &str, sort: &str) -> String {
+    match order_by_clause.strip_prefix("ORDER BY ") {
+        Some(order) => format!("ORDER BY {}, {}", sort, order),
+        None => format!("ORDER BY {}", sort),
+    }
+}
+
+// the row filter of the user (see access_ops) is AND-ed with the search
+// it is added when the statement is built , so cursors and counts see the where clause of the search only
+fn get_row_filtered_where(table: &TableConfig, where_clause: &SqlQuery) -> String {
+    match (&table.row_filter, where_clause.sql.strip_prefix("WHERE ")) {
+        (None, _) => where_clause.sql.to_string(),
+        (Some(row_filter), None) => format!("WHERE ( {} )", row_filter),
+        (Some(row_filter), Some(search)) => format!("WHERE ( {} ) AND ( {} )", row_filter, search),
+    }
+}
+
+// columns : projection (every column when empty)
+// extra_columns : computed columns returned with every row >> example : '_rank' of the fulltext search
+pub async fn build_select_query(table: &TableConfig, columns: &[String], where_clause: &SqlQuery, extra_columns: &[String], order_by_clause: &str, length: u32, start: u32) -> SqlQuery {
+    let backend_table = get_backend_table(table).await;
+    let mut table_columns = get_select_columns(table, columns).await;
+    table_columns.extend_from_slice(extra_columns);
+    SqlQuery {
+        sql: format!("SELECT {} FROM {} {} {} LIMIT {} OFFSET {}", table_columns.join(","), backend_table, get_row_filtered_where(table, where_clause), order_by_clause, length, start),
+        params: where_clause.params.clone(),
+    }
+}
+
+pub async fn build_count_query(table: &TableConfig, where_clause: &SqlQuery) -> SqlQuery {
+    let backend_table = get_backend_table(table).await;
+    SqlQuery {
+        sql: format!("SELECT count(*) FROM {} {}", backend_table, get_row_filtered_where(table, where_clause)),
+        params: where_clause.params.clone(),
+    }
+}
+
+pub async fn build_export_query(table: &TableConfig, where_clause: &SqlQuery) -> SqlQuery {
+    let backend_table = get_backend_table(table).await;
+    let table_columns = get_select_columns(table, &[]).await;
+    SqlQuery {
+        sql: format!("SELECT {} FROM {} {}", table_columns.join(","), backend_table, get_row_filtered_where(table, where_clause)),
+        params: where_clause.params.clone(),
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::fs;
+use tracing::{error, info, warn};
+use crate::data_types::{ColumnInfo, CustomError, DbPools, FullTextConfig, TableConfig, TableRegistry};
+use crate::db_ops::{get_backend_table, get_db_pool_for_table};
+use crate::access_ops::validate_access_rules;
+
+pub fn load_table_registry(file_path: &str) -> Result<TableRegistry, CustomError> {
+    let contents = match fs::read_to_string(file_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not read table registry ({}) : {}", file_path, e);
+            return Err(CustomError::ConfigError(format!("could not read table registry ({})", file_path)))
+        }
+    };
+
+    let registry: TableRegistry = match serde_yaml::from_str(contents.as_str()) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("could not parse table registry ({}) : {}", file_path, e);
+            return Err(CustomError::ConfigError(format!("could not parse table registry ({}) : {}", file_path, e)))
+        }
+    };
+
+    check_table_registry(&registry)?;
+
+    info!("table registry loaded : {} table(s) from ({})", registry.tables.len(), file_path);
+
+    Ok(registry)
+}
+
+/* ************************************************************************************* */
+
+fn check_table_registry(registry: &TableRegistry) -> Result<(), CustomError> {
+    if registry.tables.is_empty() {
+        return Err(CustomError::ConfigError("table registry does not declare any tables".to_string()))
+    }
+
+    let mut short_names = HashSet::new();
+
+    for table in &registry.tables {
+        if !short_names.insert(table.short_name.as_str()) {
+            return Err(CustomError::ConfigError(format!("table registry declares short_name ({}) more than once", table.short_name)))
+        }
+
+        let mut column_names = HashSet::new();
+        for column in &table.columns {
+            if !column_names.insert(column.as_str()) {
+                return Err(CustomError::ConfigError(format!("table ({}) declares column ({}) more than once", table.short_name, column)))
+            }
+        }
+
+        // the language ends up in the SQL (as a literal , so an index on the same expression can be used)
+        if let Some(fulltext) = &table.fulltext {
+            if fulltext.language.is_empty() || !fulltext.language.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+                return Err(CustomError::ConfigError(format!("table ({}) : invalid fulltext language ({})", table.short_name, fulltext.language)))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/* ************************************************************************************* */
+
+// makes sure every table (and every column) declared in the registry actually exists in postgres
+// and reads the type of every column from information_schema
+// (tables without 'columns' in the registry get all their columns, in table order)
+pub async fn validate_table_registry(registry: &mut TableRegistry, pools: &DbPools) -> Result<(), CustomError> {
+    for table in registry.tables.iter_mut() {
+        let pool = get_db_pool_for_table(pools, table).await?;
+
+        let client = match pool.get().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("could not connect to pool ({}) for table ({}) : {}", table.pool, table.short_name, e);
+                return Err(CustomError::DatabaseError(format!("could not connect to pool ({})", table.pool)))
+            }
+        };
+
+        let sql_query = "SELECT column_name::text, data_type::text, udt_name::text, is_nullable = 'YES' \
+                         FROM information_schema.columns WHERE table_schema = $1 AND table_name = $2 ORDER BY ordinal_position";
+
+        let rows = match client.query(sql_query, &[&table.schema, &table.backend_table]).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("could not read columns of ({}.{}) : {}", table.schema, table.backend_table, e);
+                return Err(CustomError::DatabaseError(format!("could not read columns of ({}.{})", table.schema, table.backend_table)))
+            }
+        };
+
+        if rows.is_empty() {
+            return Err(CustomError::ConfigError(format!("table ({}) : backend table ({}.{}) does not exist", table.short_name, table.schema, table.backend_table)))
+        }
+
+        let existing_columns: Vec<ColumnInfo> = rows.iter().map(|row| ColumnInfo {
+            name: row.get(0),
+            data_type: row.get(1),
+            udt_name: row.get(2),
+            is_nullable: row.get(3),
+            masked: false,
+        }).collect();
+
+        if table.columns.is_empty() {
+            table.columns = existing_columns.iter().map(|c| c.name.to_string()).collect();
+        }
+
+        let mut column_info = vec![];
+        for column in &table.columns {
+            match existing_columns.iter().find(|c| &c.name == column) {
+                Some(c) => column_info.push(c.clone()),
+                None => {
+                    return Err(CustomError::ConfigError(format!("table ({}) : column ({}) does not exist in ({}.{})", table.short_name, column, table.schema, table.backend_table)))
+                }
+            }
+        }
+        table.column_info = column_info;
+
+        table.has_pg_trgm = match client.query_opt("SELECT extname::text FROM pg_extension WHERE extname = 'pg_trgm'", &[]).await {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                error!("could not read the extensions of pool ({}) : {}", table.pool, e);
+                return Err(CustomError::DatabaseError(format!("could not read the extensions of pool ({})", table.pool)))
+            }
+        };
+        if !table.has_pg_trgm {
+            info!("table registry : ({}) pg_trgm is not installed , the fuzzy search mode is unavailable", table.short_name);
+        }
+
+        if let Some(fulltext) = table.fulltext.as_mut() {
+            validate_fulltext_config(&client, table.short_name.as_str(), &table.columns, fulltext).await?;
+        }
+
+        if table.keyset.is_some() {
+            validate_keyset_config(&client, table).await?;
+        }
+
+        validate_access_rules(&client, table).await?;
+
+        info!("table registry : ({}) => ({}.{}) validated , columns : {:?}", table.short_name, table.schema, table.backend_table, table.columns);
+    }
+
+    Ok(())
+}
+
+async fn validate_fulltext_config(client: &deadpool_postgres::Client, short_name: &str, columns: &[String], fulltext: &mut FullTextConfig) -> Result<(), CustomError> {
+    match client.query_opt("SELECT cfgname::text FROM pg_ts_config WHERE cfgname = $1", &[&fulltext.language]).await {
+        Ok(Some(_)) => (),
+        Ok(None) => return Err(CustomError::ConfigError(format!("table ({}) : unknown fulltext language ({})", short_name, fulltext.language))),
+        Err(e) => {
+            error!("could not read text search configurations : {}", e);
+            return Err(CustomError::DatabaseError("could not read text search configurations".to_string()))
+        }
+    }
+
+    for column in &fulltext.columns {
+        if !columns.contains(column) {
+            return Err(CustomError::ConfigError(format!("table ({}) : fulltext column ({}) is not a column of the table", short_name, column)))
+        }
+    }
+
+    Ok(())
+}
+
+// the key column is the tie breaker of the keyset sort , it has to be a visible , not null and unique column
+// (uniqueness is only checked on tables , a view gets a warning)
+async fn validate_keyset_config(client: &deadpool_postgres::Client, table: &TableConfig) -> Result<(), CustomError> {
+    let key_column = match &table.keyset {
+        Some(k) => k.column.as_str(),
+        None => return Ok(())
+    };
+
+    match table.column_info.iter().find(|c| c.name == key_column) {
+        Some(c) if c.is_nullable => return Err(CustomError::ConfigError(format!("table ({}) : keyset column ({}) must be NOT NULL", table.short_name, key_column))),
+        Some(_) => (),
+        None => return Err(CustomError::ConfigError(format!("table ({}) : keyset column ({}) is not a column of the table", table.short_name, key_column))),
+    }
+
+    let sql_query = "SELECT 1 FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = i.indkey[0] \
+                     WHERE i.indrelid = to_regclass($1) AND i.indisunique AND i.indnatts = 1 AND a.attname = $2";
+    let backend_table = get_backend_table(table).await;
+    match client.query_opt(sql_query, &[&backend_table, &key_column]).await {
+        Ok(Some(_)) => (),
+        Ok(None) => warn!("table ({}) : no unique index on keyset column ({}) , pages may skip or repeat rows if it is not unique", table.short_name, key_column),
+        Err(e) => {
+            error!("could not read the indexes of ({}) : {}", backend_table, e);
+            return Err(CustomError::DatabaseError(format!("could not read the indexes of ({})", backend_table)))
+        }
+    }
+
+    Ok(())
+}
+
+/* ************************************************************************************* */
+
+// unknown short names are rejected, there is no fallback table
+pub fn get_table_config<'a>(registry: &'a TableRegistry, table_short_name: &str) -> Result<&'a TableConfig, CustomError> {
+    match registry.tables.iter().find(|t| t.short_name == table_short_name) {
+        Some(table) => Ok(table),
+        None => {
+            Err(CustomError::InvalidTable(format!("unknown table ({})", table_short_name)))
+        }
+    }
+}
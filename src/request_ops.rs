@@ -0,0 +1,126 @@
+use actix_cors::Cors;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{web, Error, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{info, info_span, Instrument};
+use uuid::Uuid;
+use crate::data_types::{CustomError, ProxyConfig, RequestTable};
+use crate::db_ops::get_optional_env_var;
+use crate::log_ops::get_elapsed_ms;
+use crate::metrics_ops::observe_request;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    // id of the request being handled by the current task (set by 'with_request_id')
+    static REQUEST_ID: String;
+}
+
+// "-" outside of a request (startup , background tasks)
+pub fn get_request_id() -> String {
+    REQUEST_ID.try_with(|id| id.to_string()).unwrap_or_else(|_| "-".to_string())
+}
+
+// every request gets an id : returned in the 'X-Request-Id' header and in the JSON error bodies ,
+// so a user reporting an error can be matched with the server logs
+// an 'X-Request-Id' sent by a proxy in front of the app is reused
+// the request runs in a span (request_id , method , path , user , table) : every log event of the request carries them
+pub fn with_request_id<S, B>(req: ServiceRequest, srv: &S) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    let request_id = req.headers().get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 64)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // 'user' is recorded by with_auth , 'table' by the handlers
+    let span = info_span!("request", request_id = %request_id, method = %req.method(), path = %req.path(), user = Empty, table = Empty);
+    let started_at = Instant::now();
+
+    // the handler runs when the future is polled , both happen inside the scope of the request id
+    let future = span.in_scope(|| REQUEST_ID.sync_scope(request_id.to_string(), || srv.call(req)));
+
+    Box::pin(REQUEST_ID.scope(request_id.to_string(), async move {
+        let mut response = future.await?;
+        info!(status = response.status().as_u16(), duration_ms = get_elapsed_ms(started_at), "request finished");
+
+        // routes are labelled with their pattern (example : '/api/v1/tables/{name}/rows') , not with the path
+        let request = response.request();
+        let table = request.extensions().get::<RequestTable>().map(|t| t.0.to_string()).unwrap_or_else(|| "-".to_string());
+        observe_request(request.match_pattern().as_deref().unwrap_or("unmatched"), request.method().as_str(), table.as_str(), response.status().as_u16(), started_at.elapsed());
+        if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+            response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    }.instrument(span)))
+}
+
+// the table of the request , for the logs (span) and the metrics
+// only set once the table is known to the registry , so labels stay bounded
+pub fn set_request_table(req: &HttpRequest, table_short_name: &str) {
+    tracing::Span::current().record("table", table_short_name);
+    req.extensions_mut().insert(RequestTable(table_short_name.to_string()));
+}
+
+/* ************************************************************************************* */
+
+// Forwarded / X-Forwarded-For are sent by the client unless a proxy replaces them , they are only used when
+// PROXY.TRUST_FORWARDED=true (the app is only reachable through that proxy)
+pub fn load_proxy_config() -> Result<ProxyConfig, CustomError> {
+    let trust_forwarded = get_optional_env_var::<bool>("PROXY.TRUST_FORWARDED")?.unwrap_or(false);
+    info!("proxy : trust forwarded headers ({})", trust_forwarded);
+    Ok(ProxyConfig { trust_forwarded })
+}
+
+// the address of the client : the TCP peer , or the address forwarded by a trusted proxy
+pub fn get_client_ip(req: &HttpRequest) -> String {
+    let trust_forwarded = req.app_data::<web::Data<ProxyConfig>>().is_some_and(|p| p.trust_forwarded);
+    if trust_forwarded {
+        if let Some(ip) = req.connection_info().realip_remote_addr() {
+            return ip.to_string()
+        }
+    }
+    req.peer_addr().map_or("unknown".to_string(), |a| a.ip().to_string())
+}
+
+/* ************************************************************************************* */
+
+// CORS.ALLOWED_ORIGINS=https://reports.example.com,https://admin.example.com
+// other origins (and every origin when it is not set) can only use the app from its own pages
+pub fn load_cors_origins() -> Result<Vec<String>, CustomError> {
+    let origins: Vec<String> = get_optional_env_var::<String>("CORS.ALLOWED_ORIGINS")?
+        .unwrap_or_default()
+        .split(',')
+        .map(|o| o.trim().trim_end_matches('/').to_string())
+        .filter(|o| !o.is_empty())
+        .collect();
+
+    for origin in &origins {
+        if origin == "*" || !(origin.starts_with("https://") || origin.starts_with("http://")) {
+            return Err(CustomError::ConfigError(format!("invalid CORS.ALLOWED_ORIGINS origin ({}) , e.g. https://reports.example.com", origin)))
+        }
+    }
+
+    info!("cors : allowed origins {:?}", origins);
+    Ok(origins)
+}
+
+// credentials (session cookie , Authorization header) are allowed , so origins are always listed explicitly
+pub fn make_cors(origins: &[String]) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST", "DELETE"])
+        .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT, header::CONTENT_TYPE])
+        .expose_headers(vec![HeaderName::from_static(REQUEST_ID_HEADER)])
+        .supports_credentials()
+        .max_age(3600);
+    for origin in origins {
+        cors = cors.allowed_origin(origin.as_str());
+    }
+    cors
+}
@@ -0,0 +1,354 @@
+use std::time::{Duration, UNIX_EPOCH};
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use tokio::fs;
+use tracing::{error, info, warn};
+use crate::data_types::{CustomError, ExportFileInfo, ExportJobs, ExportListResponse, ExportMetadata, ExportPurgeParams, ExportPurgeResponse, ExportRetention};
+use crate::db_ops::get_optional_env_var;
+
+// every export is written in this directory
+pub const EXPORT_DIR: &str = "data_dir";
+// an export is written to '<file_name>.part' and renamed once complete
+pub const PARTIAL_EXPORT_SUFFIX: &str = ".part";
+const METADATA_SUFFIX: &str = ".meta.json";
+
+pub fn load_export_retention() -> Result<ExportRetention, CustomError> {
+    let ttl_secs = get_optional_env_var::<u64>("EXPORT.TTL_SECS")?.unwrap_or(24 * 60 * 60);
+    if get_chrono_duration(ttl_secs).is_none() {
+        return Err(CustomError::ConfigError(format!("EXPORT.TTL_SECS ({}) is out of range", ttl_secs)))
+    }
+    let max_total_mb = get_optional_env_var::<u64>("EXPORT.MAX_TOTAL_MB")?;
+    let sweep_interval_secs = get_optional_env_var::<u64>("EXPORT.SWEEP_INTERVAL_SECS")?.unwrap_or(5 * 60);
+
+    let retention = ExportRetention {
+        ttl: Duration::from_secs(ttl_secs),
+        max_total_bytes: max_total_mb.map(|mb| mb * 1024 * 1024),
+        sweep_interval: Duration::from_secs(sweep_interval_secs.max(1)),
+        admin_role: get_optional_env_var::<String>("EXPORT.ADMIN_ROLE")?.unwrap_or_else(|| "export_admin".to_string()),
+    };
+
+    info!("export retention : ttl ({}s) , max_total_bytes ({:?}) , sweep_interval ({}s) , admin_role ({})", ttl_secs, retention.max_total_bytes, sweep_interval_secs, retention.admin_role);
+
+    Ok(retention)
+}
+
+/* ************************************************************************************* */
+
+pub fn get_export_path(file_name: &str) -> String {
+    format!("{}/{}", EXPORT_DIR, file_name)
+}
+
+// file names come from the URL of the admin and download endpoints >> no paths , no hidden files
+pub fn is_valid_export_file_name(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && !file_name.starts_with('.')
+        && !file_name.contains('/')
+        && !file_name.contains('\\')
+        && !file_name.ends_with(METADATA_SUFFIX)
+        && !file_name.ends_with(PARTIAL_EXPORT_SUFFIX)
+}
+
+// None when the value cannot be added to a date
+fn get_chrono_duration(secs: u64) -> Option<chrono::Duration> {
+    i64::try_from(secs).ok().and_then(chrono::Duration::try_seconds)
+}
+
+// an expiry past the dates chrono can represent never comes
+fn get_expiry(metadata: &ExportMetadata, retention: &ExportRetention) -> DateTime<Utc> {
+    get_chrono_duration(retention.ttl.as_secs())
+        .and_then(|ttl| metadata.created_at.checked_add_signed(ttl))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+// the sidecar is written before the export gets its final name (see export_table_to_file)
+pub async fn write_export_metadata(metadata: &ExportMetadata) -> Result<(), CustomError> {
+    let metadata_path = get_export_path(format!("{}{}", metadata.file_name, METADATA_SUFFIX).as_str());
+    let contents = match serde_json::to_vec_pretty(metadata) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not serialize metadata of ({}) : {}", metadata.file_name, e);
+            return Err(CustomError::ExportError("could not write the export metadata".to_string()))
+        }
+    };
+    if let Err(e) = fs::write(metadata_path.as_str(), contents).await {
+        error!("could not write ({}) : {}", metadata_path, e);
+        return Err(CustomError::ExportError("could not write the export metadata".to_string()))
+    }
+    Ok(())
+}
+
+pub async fn remove_export_metadata(file_name: &str) {
+    let _ = fs::remove_file(get_export_path(format!("{}{}", file_name, METADATA_SUFFIX).as_str())).await;
+}
+
+// exports without a sidecar (written before metadata was recorded) are listed with the
+// modification time of the file as their creation time
+async fn read_export_metadata(file_name: &str, size_bytes: u64, modified: Option<std::time::SystemTime>) -> ExportMetadata {
+    let metadata_path = get_export_path(format!("{}{}", file_name, METADATA_SUFFIX).as_str());
+    if let Ok(contents) = fs::read(metadata_path.as_str()).await {
+        match serde_json::from_slice::<ExportMetadata>(&contents) {
+            Ok(mut m) => {
+                m.size_bytes = size_bytes;
+                return m
+            },
+            Err(e) => error!("could not parse ({}) : {}", metadata_path, e),
+        }
+    }
+
+    let created_at: DateTime<Utc> = modified.unwrap_or(UNIX_EPOCH).into();
+    ExportMetadata {
+        file_name: file_name.to_string(),
+        table_name: "unknown".to_string(),
+        format: None,
+        rows: None,
+        size_bytes,
+        created_by: "unknown".to_string(),
+        request_id: "-".to_string(),
+        created_at,
+    }
+}
+
+pub async fn get_export_metadata(file_name: &str) -> Result<ExportMetadata, CustomError> {
+    if !is_valid_export_file_name(file_name) {
+        return Err(CustomError::ValidationError(format!("invalid export file name ({})", file_name)))
+    }
+    match fs::metadata(get_export_path(file_name).as_str()).await {
+        Ok(m) if m.is_file() => Ok(read_export_metadata(file_name, m.len(), m.modified().ok()).await),
+        _ => Err(CustomError::NotFound(format!("unknown export ({})", file_name)))
+    }
+}
+
+// completed exports , oldest first
+pub async fn list_exports() -> Result<Vec<ExportMetadata>, CustomError> {
+    let mut entries = match fs::read_dir(EXPORT_DIR).await {
+        Ok(e) => e,
+        Err(e) => {
+            error!("could not read ({}) : {}", EXPORT_DIR, e);
+            return Err(CustomError::ExportError("could not list the exports".to_string()))
+        }
+    };
+
+    let mut exports = vec![];
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !is_valid_export_file_name(file_name.as_str()) {
+            continue
+        }
+        let file_metadata = match entry.metadata().await {
+            Ok(m) if m.is_file() => m,
+            _ => continue
+        };
+        exports.push(read_export_metadata(file_name.as_str(), file_metadata.len(), file_metadata.modified().ok()).await);
+    }
+
+    exports.sort_by_key(|m| m.created_at);
+    Ok(exports)
+}
+
+// removes the export and its sidecar , returns the size of the export
+pub async fn delete_export(file_name: &str) -> Result<u64, CustomError> {
+    if !is_valid_export_file_name(file_name) {
+        return Err(CustomError::ValidationError(format!("invalid export file name ({})", file_name)))
+    }
+
+    let file_path = get_export_path(file_name);
+    let size_bytes = match fs::metadata(file_path.as_str()).await {
+        Ok(m) if m.is_file() => m.len(),
+        _ => return Err(CustomError::NotFound(format!("unknown export ({})", file_name)))
+    };
+
+    if let Err(e) = fs::remove_file(file_path.as_str()).await {
+        error!("could not delete ({}) : {}", file_path, e);
+        return Err(CustomError::ExportError(format!("could not delete export ({})", file_name)))
+    }
+    remove_export_metadata(file_name).await;
+
+    info!("export deleted : {} ({} bytes)", file_name, size_bytes);
+
+    Ok(size_bytes)
+}
+
+// every file of data_dir (exports , partial exports , metadata) : (files , bytes)
+pub async fn get_export_dir_usage() -> Result<(u64, u64), CustomError> {
+    let mut entries = match fs::read_dir(EXPORT_DIR).await {
+        Ok(e) => e,
+        Err(e) => {
+            error!("could not read ({}) : {}", EXPORT_DIR, e);
+            return Err(CustomError::ExportError("could not read the export directory".to_string()))
+        }
+    };
+
+    let (mut files, mut bytes) = (0, 0);
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Ok(m) = entry.metadata().await {
+            if m.is_file() {
+                files += 1;
+                bytes += m.len();
+            }
+        }
+    }
+    Ok((files, bytes))
+}
+
+/* ************************************************************************************* */
+
+// new exports are refused while data_dir is above its quota (the sweeper frees space)
+pub async fn check_export_quota(retention: &ExportRetention) -> Result<(), CustomError> {
+    let max_total_bytes = match retention.max_total_bytes {
+        Some(m) => m,
+        None => return Ok(())
+    };
+    let total_bytes: u64 = list_exports().await?.iter().map(|m| m.size_bytes).sum();
+    if total_bytes >= max_total_bytes {
+        warn!("export quota exceeded : {} / {} bytes", total_bytes, max_total_bytes);
+        return Err(CustomError::QuotaExceeded("export storage is full, please try again later".to_string()))
+    }
+    Ok(())
+}
+
+// 1. exports older than the TTL are deleted
+// 2. while data_dir is above its quota the oldest exports are deleted
+// 3. partial exports (left behind by a crash) older than the TTL are deleted
+// 4. finished jobs older than the TTL are forgotten
+pub async fn sweep_exports(retention: &ExportRetention, jobs: &ExportJobs) -> Result<(), CustomError> {
+    let now = Utc::now();
+    let mut remaining = vec![];
+    let mut deleted = 0;
+
+    for export in list_exports().await? {
+        if get_expiry(&export, retention) <= now {
+            if delete_export(export.file_name.as_str()).await.is_ok() {
+                deleted += 1;
+            }
+        } else {
+            remaining.push(export);
+        }
+    }
+
+    if let Some(max_total_bytes) = retention.max_total_bytes {
+        let mut total_bytes: u64 = remaining.iter().map(|m| m.size_bytes).sum();
+        for export in &remaining {
+            if total_bytes <= max_total_bytes {
+                break
+            }
+            if let Ok(size_bytes) = delete_export(export.file_name.as_str()).await {
+                total_bytes -= size_bytes;
+                deleted += 1;
+            }
+        }
+    }
+
+    sweep_partial_exports(retention).await;
+
+    if let Ok(mut jobs) = jobs.jobs.lock() {
+        jobs.retain(|_, job| match job.finished_at {
+            Some(f) => f.elapsed() < retention.ttl,
+            None => true,
+        });
+    }
+
+    if deleted > 0 {
+        info!("export sweeper : {} export(s) deleted", deleted);
+    }
+
+    Ok(())
+}
+
+async fn sweep_partial_exports(retention: &ExportRetention) {
+    let mut entries = match fs::read_dir(EXPORT_DIR).await {
+        Ok(e) => e,
+        Err(_) => return
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(PARTIAL_EXPORT_SUFFIX) {
+            continue
+        }
+        let is_expired = match entry.metadata().await.and_then(|m| m.modified()) {
+            Ok(modified) => modified.elapsed().map(|age| age >= retention.ttl).unwrap_or(false),
+            Err(_) => false,
+        };
+        if is_expired {
+            info!("export sweeper : deleting partial export ({})", file_name);
+            let _ = fs::remove_file(entry.path()).await;
+        }
+    }
+}
+
+pub fn start_export_sweeper(retention: ExportRetention, jobs: web::Data<ExportJobs>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(retention.sweep_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = sweep_exports(&retention, &jobs).await {
+                error!("export sweeper : {}", e);
+            }
+        }
+    });
+}
+
+/* ************************************************************************************* */
+
+// can_admin : the table names whose exports are listed (see access_ops)
+pub async fn get_export_list(retention: &ExportRetention, can_admin: impl Fn(&str) -> bool) -> Result<ExportListResponse, CustomError> {
+    let mut exports = list_exports().await?;
+    exports.retain(|m| can_admin(m.table_name.as_str()));
+    let total_bytes = exports.iter().map(|m| m.size_bytes).sum();
+    Ok(ExportListResponse {
+        exports: exports.into_iter().map(|m| {
+            let expires_at = get_expiry(&m, retention);
+            ExportFileInfo { metadata: m, expires_at }
+        }).collect(),
+        total_bytes,
+        max_total_bytes: retention.max_total_bytes,
+        ttl_secs: retention.ttl.as_secs(),
+    })
+}
+
+// can_admin : the table names whose exports may be deleted (see access_ops)
+pub async fn purge_exports(params: &ExportPurgeParams, can_admin: impl Fn(&str) -> bool) -> Result<ExportPurgeResponse, CustomError> {
+    // exports created after 'created_before' are kept
+    let created_before = match params.older_than_secs {
+        Some(secs) => match get_chrono_duration(secs).and_then(|d| Utc::now().checked_sub_signed(d)) {
+            Some(t) => Some(t),
+            None => return Err(CustomError::ValidationError(format!("older_than_secs ({}) is out of range", secs)))
+        },
+        None => None,
+    };
+    let mut response = ExportPurgeResponse {
+        deleted: vec![],
+        failed: vec![],
+        freed_bytes: 0,
+    };
+
+    for export in list_exports().await? {
+        if let Some(created_before) = created_before {
+            if export.created_at > created_before {
+                continue
+            }
+        }
+        if let Some(table_name) = &params.table_name {
+            if &export.table_name != table_name {
+                continue
+            }
+        }
+        if !can_admin(export.table_name.as_str()) {
+            continue
+        }
+        // one undeletable file must not stop the purge , it is reported in 'failed'
+        match delete_export(export.file_name.as_str()).await {
+            Ok(freed_bytes) => {
+                response.freed_bytes += freed_bytes;
+                response.deleted.push(export.file_name);
+            },
+            Err(e) => {
+                error!("export purge : ({}) not deleted : {}", export.file_name, e);
+                response.failed.push(export.file_name);
+            }
+        }
+    }
+
+    info!("exports purged : {} file(s) , {} bytes , {} failed", response.deleted.len(), response.freed_bytes, response.failed.len());
+
+    Ok(response)
+}
@@ -0,0 +1,122 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use rust_decimal::Decimal;
+use serde_json::{Map, Value};
+use tokio_postgres::types::{FromSql, Kind, Type};
+use tokio_postgres::Row;
+use uuid::Uuid;
+use tracing::error;
+
+// postgres types (udt_name) that are converted natively into JSON values,
+// columns of any other type are selected as '::text' (see get_select_columns)
+const NATIVE_TYPES: [&str; 19] = [
+    "bool",
+    "int2", "int4", "int8", "oid",
+    "float4", "float8", "numeric",
+    "text", "varchar", "bpchar", "name",
+    "date", "time", "timestamp", "timestamptz",
+    "uuid", "json", "jsonb",
+];
+
+// arrays have the udt_name of their element type prefixed with '_' >> example : '_int4'
+pub fn is_native_type(udt_name: &str) -> bool {
+    NATIVE_TYPES.contains(&udt_name.trim_start_matches('_'))
+}
+
+/* ************************************************************************************* */
+
+fn get_value<'a, T: FromSql<'a>>(row: &'a Row, index: usize, to_json: fn(T) -> Value) -> Value {
+    match row.try_get::<_, Option<T>>(index) {
+        Ok(Some(v)) => to_json(v),
+        Ok(None) => Value::Null,
+        Err(e) => {
+            error!("could not convert column ({}) : {}", row.columns()[index].name(), e);
+            Value::Null
+        }
+    }
+}
+
+fn get_array_value<'a, T: FromSql<'a>>(row: &'a Row, index: usize, to_json: fn(T) -> Value) -> Value {
+    match row.try_get::<_, Option<Vec<Option<T>>>>(index) {
+        Ok(Some(items)) => Value::Array(items.into_iter().map(|i| i.map(to_json).unwrap_or(Value::Null)).collect()),
+        Ok(None) => Value::Null,
+        Err(e) => {
+            error!("could not convert array column ({}) : {}", row.columns()[index].name(), e);
+            Value::Null
+        }
+    }
+}
+
+fn float_to_json(f: f64) -> Value {
+    // NaN and Infinity have no JSON representation
+    serde_json::Number::from_f64(f).map(Value::Number).unwrap_or_else(|| Value::String(f.to_string()))
+}
+
+fn get_scalar_value(row: &Row, index: usize, column_type: &Type) -> Value {
+    match *column_type {
+        Type::BOOL => get_value::<bool>(row, index, Value::from),
+        Type::INT2 => get_value::<i16>(row, index, Value::from),
+        Type::INT4 => get_value::<i32>(row, index, Value::from),
+        Type::INT8 => get_value::<i64>(row, index, Value::from),
+        Type::OID => get_value::<u32>(row, index, Value::from),
+        Type::FLOAT4 => get_value::<f32>(row, index, |f| float_to_json(f as f64)),
+        Type::FLOAT8 => get_value::<f64>(row, index, float_to_json),
+        // numeric is returned as a string, so no precision is lost on the way to the browser
+        Type::NUMERIC => get_value::<Decimal>(row, index, |d| Value::String(d.to_string())),
+        Type::DATE => get_value::<NaiveDate>(row, index, |d| Value::String(d.to_string())),
+        Type::TIME => get_value::<NaiveTime>(row, index, |t| Value::String(t.to_string())),
+        Type::TIMESTAMP => get_value::<NaiveDateTime>(row, index, |t| Value::String(t.to_string())),
+        Type::TIMESTAMPTZ => get_value::<DateTime<Utc>>(row, index, |t| Value::String(t.to_rfc3339())),
+        Type::UUID => get_value::<Uuid>(row, index, |u| Value::String(u.to_string())),
+        Type::JSON | Type::JSONB => get_value::<Value>(row, index, |v| v),
+        _ => get_value::<String>(row, index, Value::String),
+    }
+}
+
+fn get_json_value(row: &Row, index: usize) -> Value {
+    let column_type = row.columns()[index].type_();
+    match column_type.kind() {
+        Kind::Array(element_type) => match *element_type {
+            Type::BOOL => get_array_value::<bool>(row, index, Value::from),
+            Type::INT2 => get_array_value::<i16>(row, index, Value::from),
+            Type::INT4 => get_array_value::<i32>(row, index, Value::from),
+            Type::INT8 => get_array_value::<i64>(row, index, Value::from),
+            Type::OID => get_array_value::<u32>(row, index, Value::from),
+            Type::FLOAT4 => get_array_value::<f32>(row, index, |f| float_to_json(f as f64)),
+            Type::FLOAT8 => get_array_value::<f64>(row, index, float_to_json),
+            Type::NUMERIC => get_array_value::<Decimal>(row, index, |d| Value::String(d.to_string())),
+            Type::DATE => get_array_value::<NaiveDate>(row, index, |d| Value::String(d.to_string())),
+            Type::TIME => get_array_value::<NaiveTime>(row, index, |t| Value::String(t.to_string())),
+            Type::TIMESTAMP => get_array_value::<NaiveDateTime>(row, index, |t| Value::String(t.to_string())),
+            Type::TIMESTAMPTZ => get_array_value::<DateTime<Utc>>(row, index, |t| Value::String(t.to_rfc3339())),
+            Type::UUID => get_array_value::<Uuid>(row, index, |u| Value::String(u.to_string())),
+            Type::JSON | Type::JSONB => get_array_value::<Value>(row, index, |v| v),
+            _ => get_array_value::<String>(row, index, Value::String),
+        },
+        _ => get_scalar_value(row, index, column_type),
+    }
+}
+
+/* ************************************************************************************* */
+
+// values of a row, in the order of the selected columns
+pub fn row_to_json_values(row: &Row) -> Vec<Value> {
+    (0..row.columns().len()).map(|index| get_json_value(row, index)).collect()
+}
+
+// { "column_name": value, ... } >> one entry of the DataTables 'data' array
+pub fn row_to_json_object(row: &Row) -> Value {
+    let mut object = Map::new();
+    for (index, column) in row.columns().iter().enumerate() {
+        object.insert(column.name().to_string(), get_json_value(row, index));
+    }
+    Value::Object(object)
+}
+
+// text written into a CSV cell
+pub fn json_value_to_csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(s) => s.to_string(),
+        other => other.to_string(),
+    }
+}
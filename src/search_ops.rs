@@ -0,0 +1,671 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+use crate::data_types::{ColumnInfo, CompareOp, CustomError, SearchExpr, SearchFilters, SearchOp, SearchTerm};
+use crate::fulltext_ops::{get_document_columns, get_fulltext_condition};
+use crate::query_ops::SqlQuery;
+use crate::string_ops::{get_valid_regex_pattern, quote_identifier, sanitize_search_string};
+
+// search box syntax
+//
+//   xxx yyy  |  xxx + yyy  |  xxx AND yyy      both terms
+//   xxx | yyy  |  xxx OR yyy                   either term
+//   -xxx  |  NOT xxx                           rows without the term
+//   ( ... )                                    grouping >> 'AND' binds tighter than 'OR'
+//   "a phrase"                                 spaces and operators are part of the term
+//   column:value                               only that column
+//   column:>500  (>= , < , <= , = , !=)        comparison , using the type of the column
+//   column:2022-01..2022-06  (500.. , ..500)   inclusive range , partial dates cover the whole year / month
+//
+// 'AND' , 'OR' and 'NOT' are operators only in upper case
+
+const MAX_SEARCH_LENGTH: usize = 1024;
+const MAX_SEARCH_TOKENS: usize = 128;
+const MAX_SEARCH_TERMS: usize = 32;
+// parentheses and 'NOT' (or '-') both nest the expression
+const MAX_SEARCH_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term(SearchTerm),
+}
+
+// not logged here , the error is logged once with the response (see CustomError::log)
+fn get_search_error(msg: &str) -> CustomError {
+    CustomError::ValidationError(msg.to_string())
+}
+
+// returns None when there is nothing to search
+// 'columns' are the columns a 'column:value' term can refer to
+pub fn parse_search_expression(input: &str, columns: &[String], pattern_match: &str) -> Result<Option<SearchExpr>, CustomError> {
+    if input.chars().count() > MAX_SEARCH_LENGTH {
+        return Err(get_search_error(format!("a search cannot be longer than {} characters", MAX_SEARCH_LENGTH).as_str()))
+    }
+
+    // 'regex' : the whole input is one pattern ('|' , '(' , '+' ... are regex operators , not search operators)
+    if pattern_match == "regex" {
+        if input.trim().is_empty() {
+            return Ok(None)
+        }
+        let pattern = get_valid_regex_pattern(input)?;
+        return Ok(Some(SearchExpr::Term(SearchTerm { column: None, op: SearchOp::Match(pattern) })))
+    }
+
+    let tokens = tokenize(input, columns)?;
+    if tokens.iter().filter(|t| matches!(t, Token::Term(_))).count() > MAX_SEARCH_TERMS {
+        return Err(get_search_error(format!("a search cannot contain more than {} terms", MAX_SEARCH_TERMS).as_str()))
+    }
+
+    let mut parser = SearchParser { tokens, pos: 0, depth: 0 };
+    let expr = parser.parse_or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(get_search_error("unexpected ')' in the search"))
+    }
+
+    Ok(expr)
+}
+
+/* ************************************************************************************* */
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '+' | '|' | '"')
+}
+
+// chars[start] is the opening quote , returns the phrase and the position after the closing quote
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize), CustomError> {
+    let mut phrase = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                phrase.push(chars[i + 1]);
+                i += 2;
+            },
+            '"' => return Ok((phrase, i + 1)),
+            c => {
+                phrase.push(c);
+                i += 1;
+            },
+        }
+    }
+    Err(get_search_error("a quoted phrase is missing its closing '\"'"))
+}
+
+fn tokenize(input: &str, columns: &[String]) -> Result<Vec<Token>, CustomError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue
+        }
+        if tokens.len() >= MAX_SEARCH_TOKENS {
+            return Err(get_search_error(format!("a search cannot contain more than {} terms and operators", MAX_SEARCH_TOKENS).as_str()))
+        }
+        match c {
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            },
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            },
+            '+' => {
+                tokens.push(Token::And);
+                i += 1;
+            },
+            '|' => {
+                tokens.push(Token::Or);
+                i += 1;
+            },
+            // '-xyz' , '-"a phrase"' , '-(...)' >> a '-' on its own is searched as is
+            '-' if i + 1 < chars.len() && !chars[i + 1].is_whitespace() && !matches!(chars[i + 1], ')' | '+' | '|') => {
+                tokens.push(Token::Not);
+                i += 1;
+            },
+            '"' => {
+                let (phrase, next) = read_quoted(&chars, i)?;
+                if !phrase.trim().is_empty() {
+                    tokens.push(Token::Term(SearchTerm { column: None, op: SearchOp::Match(phrase) }));
+                }
+                i = next;
+            },
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_separator(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => match word.split_once(':') {
+                        // 'column:value' , 'column:"a phrase"' , 'column:>"2022-01-01 10:00"'
+                        // a prefix that is not a column (example : 'http://') is part of the term
+                        Some((column, value)) if columns.iter().any(|c| c == column) => {
+                            if i < chars.len() && chars[i] == '"' {
+                                let (phrase, next) = read_quoted(&chars, i)?;
+                                i = next;
+                                if value.is_empty() {
+                                    Token::Term(SearchTerm { column: Some(column.to_string()), op: SearchOp::Match(phrase) })
+                                } else {
+                                    Token::Term(parse_column_term(column, format!("{}{}", value, phrase).as_str())?)
+                                }
+                            } else {
+                                Token::Term(parse_column_term(column, value)?)
+                            }
+                        },
+                        _ => Token::Term(SearchTerm { column: None, op: SearchOp::Match(word) }),
+                    },
+                };
+                tokens.push(token);
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_column_term(column: &str, value: &str) -> Result<SearchTerm, CustomError> {
+    let operators = [(">=", CompareOp::Ge), ("<=", CompareOp::Le), ("!=", CompareOp::Ne), (">", CompareOp::Gt), ("<", CompareOp::Lt), ("=", CompareOp::Eq)];
+
+    let op = if let Some((op, rest)) = operators.iter().find_map(|(prefix, op)| value.strip_prefix(prefix).map(|rest| (*op, rest))) {
+        if rest.is_empty() {
+            return Err(get_search_error(format!("missing value after ({}:{})", column, value).as_str()))
+        }
+        SearchOp::Compare(op, rest.to_string())
+    } else if let Some((low, high)) = value.split_once("..") {
+        if low.is_empty() && high.is_empty() {
+            return Err(get_search_error(format!("a range needs at least one bound ({}:{})", column, value).as_str()))
+        }
+        let bound = |b: &str| if b.is_empty() { None } else { Some(b.to_string()) };
+        SearchOp::Range(bound(low), bound(high))
+    } else if value.is_empty() {
+        return Err(get_search_error(format!("missing value after ({}:)", column).as_str()))
+    } else {
+        SearchOp::Match(value.to_string())
+    };
+
+    Ok(SearchTerm { column: Some(column.to_string()), op })
+}
+
+/* ************************************************************************************* */
+
+// or    := and ( OR and )*
+// and   := unary ( [AND] unary )*      >> terms next to each other are AND-ed
+// unary := NOT unary | '(' or ')' | term
+// dangling operators (example : 'xyz +') are ignored
+struct SearchParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl SearchParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    // every nesting level is a recursion of the parser , and later of get_search_condition
+    fn enter(&mut self) -> Result<(), CustomError> {
+        self.depth += 1;
+        if self.depth > MAX_SEARCH_DEPTH {
+            return Err(get_search_error(format!("a search cannot nest more than {} levels of parentheses and 'NOT'", MAX_SEARCH_DEPTH).as_str()))
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Option<SearchExpr>, CustomError> {
+        let mut items = vec![];
+        loop {
+            if let Some(e) = self.parse_and()? {
+                items.push(e);
+            }
+            if self.peek() != Some(&Token::Or) {
+                break
+            }
+            self.pos += 1;
+        }
+        Ok(combine(items, SearchExpr::Or))
+    }
+
+    fn parse_and(&mut self) -> Result<Option<SearchExpr>, CustomError> {
+        let mut items = vec![];
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Or) | Some(Token::RParen) | None => break,
+                _ => items.push(self.parse_unary()?),
+            }
+        }
+        Ok(combine(items, SearchExpr::And))
+    }
+
+    fn parse_unary(&mut self) -> Result<SearchExpr, CustomError> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Not) => match self.peek() {
+                Some(Token::Not) | Some(Token::LParen) | Some(Token::Term(_)) => {
+                    self.enter()?;
+                    let inner = self.parse_unary()?;
+                    self.depth -= 1;
+                    Ok(SearchExpr::Not(Box::new(inner)))
+                },
+                _ => Err(get_search_error("'NOT' (or '-') must be followed by a search term")),
+            },
+            Some(Token::LParen) => {
+                self.enter()?;
+                let inner = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(get_search_error("missing ')' in the search"))
+                }
+                self.pos += 1;
+                self.depth -= 1;
+                inner.ok_or_else(|| get_search_error("empty parentheses in the search"))
+            },
+            Some(Token::Term(t)) => Ok(SearchExpr::Term(t)),
+            _ => Err(get_search_error("invalid search")),
+        }
+    }
+}
+
+fn combine(mut items: Vec<SearchExpr>, make: fn(Vec<SearchExpr>) -> SearchExpr) -> Option<SearchExpr> {
+    match items.len() {
+        0 => None,
+        1 => items.pop(),
+        _ => Some(make(items)),
+    }
+}
+
+/* ************************************************************************************* */
+
+// how the values of a column are compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Integer,
+    Decimal,
+    Text,
+    Bool,
+    Date,
+    Timestamp,
+    Time,
+    Uuid,
+    Other,
+}
+
+fn get_column_kind(column: &ColumnInfo) -> ColumnKind {
+    match column.udt_name.as_str() {
+        "int2" | "int4" | "int8" | "oid" => ColumnKind::Integer,
+        "float4" | "float8" | "numeric" => ColumnKind::Decimal,
+        "text" | "varchar" | "bpchar" | "name" => ColumnKind::Text,
+        "bool" => ColumnKind::Bool,
+        "date" => ColumnKind::Date,
+        "timestamp" | "timestamptz" => ColumnKind::Timestamp,
+        "time" => ColumnKind::Time,
+        "uuid" => ColumnKind::Uuid,
+        _ => ColumnKind::Other,
+    }
+}
+
+// columns searched as text (text columns and types without a native comparison , json , arrays ...)
+pub fn is_text_search_column(column: &ColumnInfo) -> bool {
+    matches!(get_column_kind(column), ColumnKind::Text | ColumnKind::Other)
+}
+
+// terms the rows are ranked by (terms under 'NOT' are left out)
+pub fn get_positive_terms(expr: &SearchExpr, terms: &mut Vec<String>) {
+    match expr {
+        SearchExpr::And(items) | SearchExpr::Or(items) => items.iter().for_each(|e| get_positive_terms(e, terms)),
+        SearchExpr::Not(_) => (),
+        SearchExpr::Term(t) => {
+            if let SearchOp::Match(value) = &t.op {
+                terms.push(value.to_string());
+            }
+        },
+    }
+}
+
+// 'yyyy' , 'yyyy-mm' , 'yyyy-mm-dd' >> first day of the period , first day after the period
+fn get_date_bounds(value: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let parts: Vec<&str> = value.split('-').collect();
+    let is_number = |p: &str, len: usize| p.len() == len && p.chars().all(|c| c.is_ascii_digit());
+    match parts.as_slice() {
+        [y] if is_number(y, 4) => {
+            let y: i32 = y.parse().ok()?;
+            Some((NaiveDate::from_ymd_opt(y, 1, 1)?, NaiveDate::from_ymd_opt(y + 1, 1, 1)?))
+        },
+        [y, m] if is_number(y, 4) && is_number(m, 2) => {
+            let (y, m): (i32, u32) = (y.parse().ok()?, m.parse().ok()?);
+            let start = NaiveDate::from_ymd_opt(y, m, 1)?;
+            let end = if m == 12 { NaiveDate::from_ymd_opt(y + 1, 1, 1)? } else { NaiveDate::from_ymd_opt(y, m + 1, 1)? };
+            Some((start, end))
+        },
+        [y, m, d] if is_number(y, 4) && is_number(m, 2) && is_number(d, 2) => {
+            let start = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+            Some((start, start.succ_opt()?))
+        },
+        _ => None,
+    }
+}
+
+fn is_valid_timestamp(value: &str) -> bool {
+    let formats = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"];
+    chrono::DateTime::parse_from_rfc3339(value).is_ok()
+        || formats.iter().any(|f| NaiveDateTime::parse_from_str(value, f).is_ok())
+}
+
+fn is_valid_bool(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "true" | "false" | "t" | "f" | "yes" | "no" | "y" | "n" | "1" | "0")
+}
+
+// values the column can be compared with (comparisons , ranges and typed matches)
+fn is_valid_value(column: &ColumnInfo, op: CompareOp, value: &str) -> bool {
+    match get_column_kind(column) {
+        ColumnKind::Integer => value.parse::<i64>().is_ok() || value.parse::<Decimal>().is_ok() || Decimal::from_scientific(value).is_ok(),
+        ColumnKind::Decimal => value.parse::<f64>().is_ok(),
+        ColumnKind::Text => true,
+        ColumnKind::Bool => matches!(op, CompareOp::Eq | CompareOp::Ne) && is_valid_bool(value),
+        ColumnKind::Date => get_date_bounds(value).is_some(),
+        ColumnKind::Timestamp => get_date_bounds(value).is_some() || is_valid_timestamp(value),
+        ColumnKind::Time => NaiveTime::parse_from_str(value, "%H:%M:%S%.f").is_ok() || NaiveTime::parse_from_str(value, "%H:%M").is_ok(),
+        ColumnKind::Uuid => Uuid::parse_str(value).is_ok(),
+        ColumnKind::Other => false,
+    }
+}
+
+fn get_sql_operator(op: CompareOp) -> &'static str {
+    match op {
+        CompareOp::Eq => "=",
+        CompareOp::Ne => "<>",
+        CompareOp::Gt => ">",
+        CompareOp::Ge => ">=",
+        CompareOp::Lt => "<",
+        CompareOp::Le => "<=",
+    }
+}
+
+// the value is bound as text and cast to the type of the column , so postgres compares natively
+// (and an index on the column can be used)
+fn get_cast_placeholder(query: &mut SqlQuery, value: String, column: &ColumnInfo) -> String {
+    format!("{}::text::{}", query.bind(value), column.udt_name)
+}
+
+// integer column compared with a fractional (or out of range) value >> compared as numeric
+fn get_integer_cast(query: &mut SqlQuery, value: &str, column: &ColumnInfo) -> Option<String> {
+    let fits = match column.udt_name.as_str() {
+        "int2" => value.parse::<i16>().is_ok(),
+        "int4" => value.parse::<i32>().is_ok(),
+        "int8" => value.parse::<i64>().is_ok(),
+        "oid" => value.parse::<u32>().is_ok(),
+        _ => false,
+    };
+    if fits {
+        return Some(get_cast_placeholder(query, value.to_string(), column))
+    }
+    value.parse::<Decimal>().ok()
+        .or_else(|| Decimal::from_scientific(value).ok())
+        .map(|d| format!("{}::text::numeric", query.bind(d.to_string())))
+}
+
+// a partial date covers a period >> my_date:2022-06 is '>= 2022-06-01 AND < 2022-07-01'
+fn get_period_comparison(query: &mut SqlQuery, column: &ColumnInfo, op: CompareOp, (start, end): (NaiveDate, NaiveDate)) -> String {
+    let column_name = quote_identifier(column.name.as_str());
+    // only the bounds used by the comparison are bound (postgres rejects unused parameters)
+    let mut bind = |date: NaiveDate| if get_column_kind(column) == ColumnKind::Text {
+        format!("{}::text", query.bind(date.to_string()))
+    } else {
+        get_cast_placeholder(query, date.to_string(), column)
+    };
+    match op {
+        CompareOp::Eq => format!(" ( {c} >= {} AND {c} < {} ) ", bind(start), bind(end), c = column_name),
+        CompareOp::Ne => format!(" ( {c} < {} OR {c} >= {} ) ", bind(start), bind(end), c = column_name),
+        CompareOp::Gt => format!(" {} >= {} ", column_name, bind(end)),
+        CompareOp::Ge => format!(" {} >= {} ", column_name, bind(start)),
+        CompareOp::Lt => format!(" {} < {} ", column_name, bind(start)),
+        CompareOp::Le => format!(" {} < {} ", column_name, bind(end)),
+    }
+}
+
+fn get_comparison(query: &mut SqlQuery, column: &ColumnInfo, op: CompareOp, value: &str) -> Result<String, CustomError> {
+    let column_name = quote_identifier(column.name.as_str());
+    let kind = get_column_kind(column);
+
+    if kind == ColumnKind::Other {
+        return Err(get_search_error(format!("column ({}) of type ({}) does not support comparisons", column.name, column.udt_name).as_str()))
+    }
+    if !is_valid_value(column, op, value) {
+        return Err(get_search_error(format!("invalid value ({}) for column ({}) of type ({})", value, column.name, column.udt_name).as_str()))
+    }
+
+    // dates stored as 'yyyy-mm-dd' text compare like dates
+    if matches!(kind, ColumnKind::Text | ColumnKind::Date | ColumnKind::Timestamp) {
+        if let Some(bounds) = get_date_bounds(value) {
+            return Ok(get_period_comparison(query, column, op, bounds))
+        }
+    }
+
+    let placeholder = match kind {
+        ColumnKind::Integer => match get_integer_cast(query, value, column) {
+            Some(p) => p,
+            None => return Err(get_search_error(format!("invalid value ({}) for column ({}) of type ({})", value, column.name, column.udt_name).as_str()))
+        },
+        ColumnKind::Text => format!("{}::text", query.bind(value.to_string())),
+        _ => get_cast_placeholder(query, value.to_string(), column),
+    };
+
+    Ok(format!(" {} {} {} ", column_name, get_sql_operator(op), placeholder))
+}
+
+// a term matches a row if it matches any of the columns :
+// - text columns : 'like' (ILIKE '%term%') , 'exact' (case insensitive equality) , 'fuzzy' (pg_trgm '%')
+//   or 'regex' (~* , only text columns are matched)
+// - typed columns : equality , only when the term is a value of their type (example : '500' is looked up
+//   in integer columns , 'abc' is not) , partial dates match the whole year / month / day
+// - any other type (json , arrays , inet ...) : compared as text
+// the columns keep their own type , so postgres can use their indexes
+fn get_match_condition(query: &mut SqlQuery, columns: &[&ColumnInfo], value: &str, pattern_match: &str) -> Result<String, CustomError> {
+    let mut text_placeholder = None;
+    let mut column_conditions = vec![];
+
+    for column in columns {
+        let column_name = quote_identifier(column.name.as_str());
+        let kind = get_column_kind(column);
+        match kind {
+            ColumnKind::Text | ColumnKind::Other => {
+                let placeholder = match &text_placeholder {
+                    Some(p) => p,
+                    None => text_placeholder.insert(query.bind(sanitize_search_string(value, pattern_match)?)),
+                }.to_string();
+                let column_text = if kind == ColumnKind::Text { column_name } else { format!("{}::text", column_name) };
+                column_conditions.push(match pattern_match {
+                    "exact" => format!(" lower({}) = lower({}) ", column_text, placeholder),
+                    // pg_trgm : similarity above pg_trgm.similarity_threshold (SEARCH.FUZZY_THRESHOLD)
+                    "fuzzy" => format!(" {} % {} ", column_text, placeholder),
+                    "regex" => format!(" {} ~* {} ", column_text, placeholder),
+                    _ => format!(" {} ILIKE {} ", column_text, placeholder),
+                });
+            },
+            // a regex is only matched with the text columns
+            _ if pattern_match == "regex" => (),
+            // '1' or 'yes' in the search box should not match every boolean column
+            ColumnKind::Bool if !matches!(value.to_lowercase().as_str(), "true" | "false") => (),
+            _ if is_valid_value(column, CompareOp::Eq, value) => column_conditions.push(get_comparison(query, column, CompareOp::Eq, value)?),
+            _ => (),
+        }
+    }
+
+    // none of the columns can hold the term
+    if column_conditions.is_empty() {
+        return Ok(" FALSE ".to_string())
+    }
+
+    Ok(format!(" ( {} ) ", column_conditions.join(" OR ")))
+}
+
+fn get_term_condition(query: &mut SqlQuery, term: &SearchTerm, filters: &SearchFilters, default_columns: &[String], pattern_match: &str) -> Result<String, CustomError> {
+    let column = match &term.column {
+        Some(name) => match filters.columns.iter().find(|c| &c.name == name) {
+            Some(c) if c.masked => return Err(get_search_error(format!("column ({}) is masked and cannot be searched", name).as_str())),
+            Some(c) => Some(c),
+            None => return Err(get_search_error(format!("unknown column ({})", name).as_str()))
+        },
+        None => None,
+    };
+
+    match (&term.op, column) {
+        // 'column:value' with a value the column cannot hold is an error (a global term just skips the column)
+        (SearchOp::Match(value), Some(c)) => {
+            if !matches!(get_column_kind(c), ColumnKind::Text | ColumnKind::Other) && !is_valid_value(c, CompareOp::Eq, value) {
+                return Err(get_search_error(format!("invalid value ({}) for column ({}) of type ({})", value, c.name, c.udt_name).as_str()))
+            }
+            if pattern_match == "fulltext" && matches!(get_column_kind(c), ColumnKind::Text | ColumnKind::Other) {
+                return Ok(get_fulltext_condition(query, &filters.fulltext, &[c.name.to_string()], value))
+            }
+            get_match_condition(query, &[c], value, pattern_match)
+        },
+        // 'fulltext' : the term is only looked up in the document (the fulltext columns)
+        (SearchOp::Match(value), None) if pattern_match == "fulltext" => {
+            Ok(get_fulltext_condition(query, &filters.fulltext, &get_document_columns(&filters.fulltext, default_columns), value))
+        },
+        (SearchOp::Match(value), None) => {
+            let default_columns: Vec<&ColumnInfo> = filters.columns.iter().filter(|c| default_columns.contains(&c.name) && !c.masked).collect();
+            get_match_condition(query, &default_columns, value, pattern_match)
+        },
+        (SearchOp::Compare(op, value), Some(c)) => get_comparison(query, c, *op, value),
+        (SearchOp::Range(low, high), Some(c)) => {
+            let mut bounds = vec![];
+            if let Some(low) = low {
+                bounds.push(get_comparison(query, c, CompareOp::Ge, low)?);
+            }
+            if let Some(high) = high {
+                bounds.push(get_comparison(query, c, CompareOp::Le, high)?);
+            }
+            Ok(format!(" ( {} ) ", bounds.join(" AND ")))
+        },
+        (_, None) => Err(get_search_error("comparisons and ranges need a column (example : 'column:>500')")),
+    }
+}
+
+// returns the boolean expression used in the WHERE clause , every value is bound as a parameter of 'query'
+// filters : every column of the table and the fulltext config , default_columns : columns searched by terms without 'column:'
+// pattern_match : 'exact' | 'like' | 'fulltext' | 'fuzzy' | 'regex' (how a term is compared with text columns)
+pub fn get_search_condition(query: &mut SqlQuery, expr: &SearchExpr, filters: &SearchFilters, default_columns: &[String], pattern_match: &str) -> Result<String, CustomError> {
+    match expr {
+        SearchExpr::And(items) | SearchExpr::Or(items) => {
+            let joined_by = if matches!(expr, SearchExpr::And(_)) { " AND " } else { " OR " };
+            let conditions = items.iter()
+                .map(|e| get_search_condition(query, e, filters, default_columns, pattern_match))
+                .collect::<Result<Vec<String>, CustomError>>()?;
+            Ok(format!(" ( {} ) ", conditions.join(joined_by)))
+        },
+        // a NULL column does not contain the term
+        SearchExpr::Not(inner) => Ok(format!(" NOT coalesce({}, FALSE) ", get_search_condition(query, inner, filters, default_columns, pattern_match)?)),
+        SearchExpr::Term(term) => get_term_condition(query, term, filters, default_columns, pattern_match),
+    }
+}
+
+/* ************************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Option<SearchExpr>, CustomError> {
+        parse_search_expression(input, &["random_num".to_string(), "my_date".to_string()], "like")
+    }
+
+    fn term(value: &str) -> SearchExpr {
+        SearchExpr::Term(SearchTerm { column: None, op: SearchOp::Match(value.to_string()) })
+    }
+
+    fn range(column: &str, low: Option<&str>, high: Option<&str>) -> SearchExpr {
+        SearchExpr::Term(SearchTerm { column: Some(column.to_string()), op: SearchOp::Range(low.map(String::from), high.map(String::from)) })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expected = SearchExpr::Or(vec![term("a"), SearchExpr::And(vec![term("b"), term("c")])]);
+        assert_eq!(parse("a | b c").unwrap(), Some(expected.clone()));
+        assert_eq!(parse("a OR b AND c").unwrap(), Some(expected));
+        assert_eq!(parse("(a | b) + c").unwrap(), Some(SearchExpr::And(vec![SearchExpr::Or(vec![term("a"), term("b")]), term("c")])));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term_or_group() {
+        assert_eq!(parse("a -b").unwrap(), Some(SearchExpr::And(vec![term("a"), SearchExpr::Not(Box::new(term("b")))])));
+        assert_eq!(parse("NOT (a | b)").unwrap(), Some(SearchExpr::Not(Box::new(SearchExpr::Or(vec![term("a"), term("b")])))));
+        assert_eq!(parse("a - b").unwrap(), Some(SearchExpr::And(vec![term("a"), term("-"), term("b")])));
+        assert!(parse("a NOT").is_err());
+    }
+
+    #[test]
+    fn operators_are_only_upper_case_and_phrases_are_terms() {
+        assert_eq!(parse("a or b").unwrap(), Some(SearchExpr::And(vec![term("a"), term("or"), term("b")])));
+        assert_eq!(parse("\"a | b\"").unwrap(), Some(term("a | b")));
+        assert!(parse("\"a phrase").is_err());
+    }
+
+    #[test]
+    fn column_terms_and_ranges() {
+        assert_eq!(parse("random_num:>500").unwrap(), Some(SearchExpr::Term(SearchTerm { column: Some("random_num".to_string()), op: SearchOp::Compare(CompareOp::Gt, "500".to_string()) })));
+        assert_eq!(parse("my_date:2022-01..2022-06").unwrap(), Some(range("my_date", Some("2022-01"), Some("2022-06"))));
+        assert_eq!(parse("random_num:500..").unwrap(), Some(range("random_num", Some("500"), None)));
+        assert_eq!(parse("random_num:..500").unwrap(), Some(range("random_num", None, Some("500"))));
+        assert!(parse("random_num:..").is_err());
+        assert!(parse("random_num:>").is_err());
+        // not a column >> part of the term
+        assert_eq!(parse("http://x").unwrap(), Some(term("http://x")));
+    }
+
+    #[test]
+    fn parentheses_must_balance() {
+        assert!(parse("(a | b").is_err());
+        assert!(parse("a | b)").is_err());
+        assert!(parse("()").is_err());
+        assert_eq!(parse("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn depth_is_limited() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(nested(MAX_SEARCH_DEPTH).as_str()).is_ok());
+        assert!(parse(nested(MAX_SEARCH_DEPTH + 1).as_str()).is_err());
+    }
+
+    #[test]
+    fn not_chains_are_limited() {
+        assert_eq!(parse("NOT NOT a").unwrap(), Some(SearchExpr::Not(Box::new(SearchExpr::Not(Box::new(term("a")))))));
+        assert!(parse(format!("{}a", "-".repeat(MAX_SEARCH_DEPTH)).as_str()).is_ok());
+        assert!(parse(format!("{}a", "-".repeat(MAX_SEARCH_DEPTH + 1)).as_str()).is_err());
+        assert!(parse(format!("{}a", "NOT ".repeat(MAX_SEARCH_DEPTH + 1)).as_str()).is_err());
+        assert!(parse(format!("-({}a)", "NOT ".repeat(MAX_SEARCH_DEPTH)).as_str()).is_err());
+        // long enough to overflow the stack without the limits
+        assert!(parse(format!("{}a", "-".repeat(100_000)).as_str()).is_err());
+        assert!(parse(format!("{}a", "-".repeat(MAX_SEARCH_LENGTH - 1)).as_str()).is_err());
+    }
+
+    #[test]
+    fn length_and_token_count_are_limited() {
+        assert!(parse("a".repeat(MAX_SEARCH_LENGTH).as_str()).is_ok());
+        assert!(parse("a".repeat(MAX_SEARCH_LENGTH + 1).as_str()).is_err());
+        assert!(parse("+ ".repeat(MAX_SEARCH_TOKENS + 1).as_str()).is_err());
+    }
+
+    #[test]
+    fn term_count_is_limited() {
+        let terms = |count: usize| vec!["a"; count].join(" ");
+        assert!(parse(terms(MAX_SEARCH_TERMS).as_str()).is_ok());
+        assert!(parse(terms(MAX_SEARCH_TERMS + 1).as_str()).is_err());
+    }
+}
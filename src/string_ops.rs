@@ -1,32 +1,269 @@
-use regex::Regex;
+use regex::RegexBuilder;
+use crate::data_types::CustomError;
 
-pub fn get_sanitized_string(text: &str) -> String {
-    let re = Regex::new(r"[^a-zA-Z0-9-._@/,;:\s]+").unwrap();
-    let result = re.replace_all(&text, "");
-    result.to_string()
+// each search mode gets its own cleanup of the term typed by the user , the result is always bound as a parameter
+//   like  : '%' and '_' are matched literally , the term is looked up anywhere in the column
+//   regex : the pattern is validated (see get_valid_regex_pattern) and sent as is
+//   other : sent as is
+pub fn sanitize_search_string(input: &str, pattern_match: &str) -> Result<String, CustomError> {
+    match pattern_match {
+        "like" => Ok(format!("%{}%", escape_like_pattern(input))),
+        "regex" => get_valid_regex_pattern(input),
+        _ => Ok(input.to_string()),
+    }
 }
 
-/* ************************************************************************************* */
+// escapes the LIKE wildcards, so '%' and '_' typed by the user are matched literally
+pub fn escape_like_pattern(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// double quotes a postgres identifier (table / column name)
+pub fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
 
-pub async fn sanitize_string(input: &str) -> String {
-    input.chars()
-        .filter(|&c| c.is_ascii_alphanumeric() || "_./-@,#:;".contains(c))
+// a query string value (RFC 3986) , only the unreserved characters are kept as is
+pub fn percent_encode(input: &str) -> String {
+    input.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
         .collect()
 }
 
-pub async fn remove_leading_trailing_characters(input: &str) -> String {
-    input.trim_start_matches(",").trim_end_matches(",").to_string()
+/* ************************************************************************************* */
+
+const MAX_REGEX_LENGTH: usize = 256;
+const MAX_REGEX_NESTING: u32 = 16;
+const MAX_REGEX_SIZE: usize = 1 << 20;
+// postgres rejects {n,m} above 255 (DUPMAX)
+const MAX_REGEX_REPEAT: u32 = 255;
+// escapes with the same meaning for the regex crate and postgres (any escaped punctuation is a literal)
+const REGEX_ESCAPES: &str = "dDsSwWtnr";
+
+fn get_regex_error(msg: &str) -> CustomError {
+    CustomError::ValidationError(msg.to_string())
+}
+
+// the pattern is compiled with the regex crate (size and nesting limits) before it reaches postgres ('~*')
+// on top of that , constructs postgres does not support (or reads differently) are rejected :
+// named groups , inline flags , \b , \p{..} , \x.. , and nested quantifiers like (a+)+ which can make a search explode
+pub fn get_valid_regex_pattern(input: &str) -> Result<String, CustomError> {
+    let pattern = input.trim();
+    if pattern.chars().count() > MAX_REGEX_LENGTH {
+        return Err(get_regex_error(format!("a regex cannot be longer than {} characters", MAX_REGEX_LENGTH).as_str()))
+    }
+
+    let built = RegexBuilder::new(pattern)
+        .size_limit(MAX_REGEX_SIZE)
+        .nest_limit(MAX_REGEX_NESTING)
+        .build();
+    match built {
+        Ok(_) => (),
+        // the syntax errors of the regex crate span several lines (pattern , marker , message) , keep the message
+        Err(regex::Error::Syntax(e)) => {
+            let msg = e.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("syntax error").trim_start_matches("error: ");
+            return Err(get_regex_error(format!("invalid regex ({}) : {}", pattern, msg).as_str()))
+        },
+        Err(_) => return Err(get_regex_error(format!("regex ({}) is too complex", pattern).as_str())),
+    }
+
+    check_regex_constructs(pattern)?;
+    Ok(pattern.to_string())
+}
+
+// length of the quantifier at chars[start] ('*' , '+' , '?' , '{n,m}' and their lazy '?')
+fn get_quantifier_length(chars: &[char], start: usize) -> Result<usize, CustomError> {
+    let mut end = start + 1;
+    if chars[start] == '{' {
+        while end < chars.len() && chars[end] != '}' {
+            end += 1;
+        }
+        let counts: String = chars[start + 1..end.min(chars.len())].iter().collect();
+        for count in counts.split(',').filter(|c| !c.is_empty()) {
+            if count.trim().parse::<u32>().map_or(true, |n| n > MAX_REGEX_REPEAT) {
+                return Err(get_regex_error(format!("regex repetitions are limited to {}", MAX_REGEX_REPEAT).as_str()))
+            }
+        }
+        end += 1;
+    }
+    if chars.get(end) == Some(&'?') {
+        end += 1;
+    }
+    Ok(end - start)
 }
 
-pub async fn replace_multiple_characters(input: &str) -> String {
-    let re = Regex::new(r",+").unwrap();
-    re.replace_all(input, ",").to_string()
+// position after the bracket expression starting at chars[start] ('[^]a-z]' , '[[:alpha:]]' ...)
+fn skip_bracket_expression(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    if chars.get(i) == Some(&'^') {
+        i += 1;
+    }
+    // a leading ']' is a literal
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '[' if chars.get(i + 1) == Some(&':') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == ']' && chars[i - 1] == ':') {
+                    i += 1;
+                }
+                i += 1;
+            },
+            '[' => i = skip_bracket_expression(chars, i),
+            ']' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    i
 }
 
-pub async fn split_string(input: &str, split_char: &str) -> Vec<String> {
-    input.split(split_char).map(|s| s.to_string()).collect()
+fn check_regex_constructs(pattern: &str) -> Result<(), CustomError> {
+    let chars: Vec<char> = pattern.chars().collect();
+    // one entry per open group (the whole pattern first) : does it contain a quantifier
+    let mut groups = vec![false];
+    // the previous atom is a group with a quantifier inside
+    let mut quantified_group = false;
+    let mut after_quantifier = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let follows_quantifier = std::mem::take(&mut after_quantifier);
+        match chars[i] {
+            '\\' => {
+                if let Some(c) = chars.get(i + 1) {
+                    if c.is_ascii_alphanumeric() && !REGEX_ESCAPES.contains(*c) {
+                        return Err(get_regex_error(format!("unsupported regex escape (\\{})", c).as_str()))
+                    }
+                }
+                quantified_group = false;
+                i += 2;
+            },
+            '[' => {
+                quantified_group = false;
+                i = skip_bracket_expression(&chars, i);
+            },
+            '(' => {
+                if chars.get(i + 1) == Some(&'?') && chars.get(i + 2) != Some(&':') {
+                    return Err(get_regex_error("named groups and inline flags are not supported in a regex"))
+                }
+                groups.push(false);
+                quantified_group = false;
+                // '(?:' non capturing group
+                i += if chars.get(i + 1) == Some(&'?') { 3 } else { 1 };
+            },
+            ')' => {
+                quantified_group = groups.pop().unwrap_or(false);
+                if quantified_group {
+                    if let Some(parent) = groups.last_mut() {
+                        *parent = true;
+                    }
+                }
+                i += 1;
+            },
+            '*' | '+' | '?' | '{' => {
+                // 'a+*' , 'a?+' : the regex crate accepts them , postgres does not
+                if follows_quantifier {
+                    return Err(get_regex_error("a regex quantifier cannot follow another quantifier"))
+                }
+                if quantified_group && chars[i] != '?' {
+                    return Err(get_regex_error("nested quantifiers like (a+)+ are not supported in a regex"))
+                }
+                if let Some(group) = groups.last_mut() {
+                    *group = true;
+                }
+                quantified_group = false;
+                after_quantifier = true;
+                i += get_quantifier_length(&chars, i)?;
+            },
+            _ => {
+                quantified_group = false;
+                i += 1;
+            },
+        }
+    }
+    Ok(())
 }
 
-pub async fn remove_leading_and_trailing_spaces(my_str: &str) -> String {
-    my_str.trim_start().trim_end().to_string()
+/* ************************************************************************************* */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_patterns_are_trimmed_and_kept() {
+        assert_eq!(get_valid_regex_pattern("  ^ab+c$ ").unwrap(), "^ab+c$");
+        assert!(get_valid_regex_pattern("(?:ab)+").is_ok());
+        assert!(get_valid_regex_pattern("[[:alpha:]]+[^])]?").is_ok());
+        assert!(get_valid_regex_pattern("(a|b)*c").is_ok());
+    }
+
+    #[test]
+    fn stacked_quantifiers_are_rejected() {
+        for pattern in ["a+*", "(ab)?+", "a{2}+", "a+?+"] {
+            assert!(get_valid_regex_pattern(pattern).is_err(), "{}", pattern);
+        }
+        assert!(get_valid_regex_pattern("a+?b*?").is_ok());
+        assert!(get_valid_regex_pattern("\\\\*").is_ok());
+        assert!(get_valid_regex_pattern("\\+*").is_ok());
+    }
+
+    #[test]
+    fn nested_quantifiers_are_rejected() {
+        for pattern in ["(a+)+", "(a*)*", "(a+)*", "(a{2})+", "((a+)b)+", "(?:a+){2}"] {
+            assert!(get_valid_regex_pattern(pattern).is_err(), "{}", pattern);
+        }
+        // a quantified group without a quantifier inside , or a quantifier inside a group that is not repeated
+        assert!(get_valid_regex_pattern("(ab)+").is_ok());
+        assert!(get_valid_regex_pattern("(a+)b").is_ok());
+        assert!(get_valid_regex_pattern("(a+)?").is_ok());
+        // quantifiers inside a bracket expression are literals
+        assert!(get_valid_regex_pattern("([+*])+").is_ok());
+    }
+
+    #[test]
+    fn only_whitelisted_escapes_are_accepted() {
+        for escape in REGEX_ESCAPES.chars() {
+            assert!(get_valid_regex_pattern(format!("a\\{}", escape).as_str()).is_ok(), "\\{}", escape);
+        }
+        for pattern in ["\\b", "\\x41", "\\p{L}", "\\A", "\\z", "\\1"] {
+            assert!(get_valid_regex_pattern(pattern).is_err(), "{}", pattern);
+        }
+        // escaped punctuation is a literal
+        assert!(get_valid_regex_pattern("\\.\\(\\+").is_ok());
+    }
+
+    #[test]
+    fn named_groups_and_inline_flags_are_rejected() {
+        assert!(get_valid_regex_pattern("(?P<x>a)").is_err());
+        assert!(get_valid_regex_pattern("(?i)abc").is_err());
+    }
+
+    #[test]
+    fn length_and_repetitions_are_limited() {
+        assert!(get_valid_regex_pattern("a".repeat(MAX_REGEX_LENGTH).as_str()).is_ok());
+        assert!(get_valid_regex_pattern("a".repeat(MAX_REGEX_LENGTH + 1).as_str()).is_err());
+        assert!(get_valid_regex_pattern("a{255}").is_ok());
+        assert!(get_valid_regex_pattern("a{1,256}").is_err());
+        let nested = format!("{}a{}", "(".repeat(MAX_REGEX_NESTING as usize + 1), ")".repeat(MAX_REGEX_NESTING as usize + 1));
+        assert!(get_valid_regex_pattern(nested.as_str()).is_err());
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(sanitize_search_string("50%_a\\b", "like").unwrap(), "%50\\%\\_a\\\\b%");
+        assert_eq!(quote_identifier("my\"col"), "\"my\"\"col\"");
+    }
+
+    #[test]
+    fn query_values_are_percent_encoded() {
+        assert_eq!(percent_encode("/tables?t=table1&x=a b"), "%2Ftables%3Ft%3Dtable1%26x%3Da%20b");
+        assert_eq!(percent_encode("é-_.~"), "%C3%A9-_.~");
+    }
 }
@@ -0,0 +1,459 @@
+use std::sync::Arc;
+use arrow_array::builder::{ArrayBuilder, BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use csv::{Writer, WriterBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use rust_xlsxwriter::{Format, Workbook};
+use serde_json::Value;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio_postgres::types::Type;
+use tokio_postgres::{Column, Row};
+use tracing::error;
+use crate::data_types::{CustomError, ExportFormat};
+use crate::row_ops::{json_value_to_csv_field, row_to_json_object, row_to_json_values};
+
+// one implementation per export format , the rows are handed over one by one as they are
+// streamed from postgres (see export_table_to_file) and the writers only keep a chunk in memory
+#[async_trait]
+pub trait ExportWriter: Send {
+    // called once , before the first row
+    async fn write_header(&mut self, columns: &[Column]) -> Result<(), CustomError>;
+    async fn write_row(&mut self, row: &Row) -> Result<(), CustomError>;
+    // flushes everything that is still buffered , the file is complete afterwards
+    async fn finish(&mut self) -> Result<(), CustomError>;
+}
+
+pub async fn make_export_writer(format: ExportFormat, file_path: &str) -> Result<Box<dyn ExportWriter>, CustomError> {
+    match format {
+        ExportFormat::Csv => Ok(Box::new(DelimitedWriter::new(create_export_file(file_path).await?, b','))),
+        ExportFormat::Tsv => Ok(Box::new(DelimitedWriter::new(create_export_file(file_path).await?, b'\t'))),
+        ExportFormat::Jsonl => Ok(Box::new(JsonLinesWriter::new(create_export_file(file_path).await?))),
+        ExportFormat::Xlsx => Ok(Box::new(XlsxWriter::new(file_path))),
+        ExportFormat::Parquet => Ok(Box::new(ParquetWriter::new(create_export_file(file_path).await?))),
+    }
+}
+
+/* ************************************************************************************* */
+
+// number of rows buffered before they are written to the file
+const EXPORT_CHUNK_ROWS: usize = 1000;
+
+async fn create_export_file(file_path: &str) -> Result<File, CustomError> {
+    match File::create(file_path).await {
+        Ok(f) => Ok(f),
+        Err(e) => {
+            error!("could not create file ({}) : {}", file_path, e);
+            Err(CustomError::ExportError("could not create the export file".to_string()))
+        }
+    }
+}
+
+async fn write_chunk(file: &mut File, chunk: &[u8]) -> Result<(), CustomError> {
+    if let Err(e) = file.write_all(chunk).await {
+        error!("could not write export file : {}", e);
+        return Err(CustomError::ExportError("could not write the export file".to_string()))
+    }
+    Ok(())
+}
+
+async fn flush_file(file: &mut File) -> Result<(), CustomError> {
+    if let Err(e) = file.flush().await {
+        error!("could not write export file : {}", e);
+        return Err(CustomError::ExportError("could not write the export file".to_string()))
+    }
+    Ok(())
+}
+
+fn get_write_error(e: impl std::fmt::Display) -> CustomError {
+    error!("could not write export record : {}", e);
+    CustomError::ExportError("could not write the export file".to_string())
+}
+
+// encoding and compression (parquet , xlsx) are CPU work , they run on the blocking pool , not on a tokio worker
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, CustomError> + Send + 'static) -> Result<T, CustomError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => Err(get_write_error(e)),
+    }
+}
+
+/* ************************************************************************************* */
+
+// CSV (',') and TSV ('\t')
+pub struct DelimitedWriter {
+    file: File,
+    delimiter: u8,
+    wtr: Writer<Vec<u8>>,
+    chunk_rows: usize,
+}
+
+impl DelimitedWriter {
+    fn new(file: File, delimiter: u8) -> DelimitedWriter {
+        DelimitedWriter {
+            file,
+            delimiter,
+            wtr: WriterBuilder::new().delimiter(delimiter).from_writer(vec![]),
+            chunk_rows: 0,
+        }
+    }
+
+    async fn write_buffered_records(&mut self) -> Result<(), CustomError> {
+        let wtr = std::mem::replace(&mut self.wtr, WriterBuilder::new().delimiter(self.delimiter).from_writer(vec![]));
+        let chunk = wtr.into_inner().map_err(get_write_error)?;
+        self.chunk_rows = 0;
+        write_chunk(&mut self.file, &chunk).await
+    }
+}
+
+#[async_trait]
+impl ExportWriter for DelimitedWriter {
+    async fn write_header(&mut self, columns: &[Column]) -> Result<(), CustomError> {
+        let headers: Vec<&str> = columns.iter().map(|col| col.name()).collect();
+        self.wtr.write_record(&headers).map_err(get_write_error)
+    }
+
+    async fn write_row(&mut self, row: &Row) -> Result<(), CustomError> {
+        let record: Vec<String> = row_to_json_values(row).iter().map(json_value_to_csv_field).collect();
+        self.wtr.write_record(&record).map_err(get_write_error)?;
+        self.chunk_rows += 1;
+        if self.chunk_rows == EXPORT_CHUNK_ROWS {
+            self.write_buffered_records().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), CustomError> {
+        self.write_buffered_records().await?;
+        flush_file(&mut self.file).await
+    }
+}
+
+/* ************************************************************************************* */
+
+// JSON Lines (NDJSON) : one { "column_name": value, ... } object per line , same values as /query
+pub struct JsonLinesWriter {
+    file: File,
+    buffer: Vec<u8>,
+    chunk_rows: usize,
+}
+
+impl JsonLinesWriter {
+    fn new(file: File) -> JsonLinesWriter {
+        JsonLinesWriter { file, buffer: vec![], chunk_rows: 0 }
+    }
+}
+
+#[async_trait]
+impl ExportWriter for JsonLinesWriter {
+    async fn write_header(&mut self, _columns: &[Column]) -> Result<(), CustomError> {
+        Ok(())
+    }
+
+    async fn write_row(&mut self, row: &Row) -> Result<(), CustomError> {
+        serde_json::to_writer(&mut self.buffer, &row_to_json_object(row)).map_err(get_write_error)?;
+        self.buffer.push(b'\n');
+        self.chunk_rows += 1;
+        if self.chunk_rows == EXPORT_CHUNK_ROWS {
+            write_chunk(&mut self.file, &self.buffer).await?;
+            self.buffer.clear();
+            self.chunk_rows = 0;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), CustomError> {
+        write_chunk(&mut self.file, &self.buffer).await?;
+        self.buffer.clear();
+        flush_file(&mut self.file).await
+    }
+}
+
+/* ************************************************************************************* */
+
+// limits of an excel worksheet
+const XLSX_MAX_ROWS: u32 = 1_048_576;
+const XLSX_MAX_STRING_LENGTH: usize = 32_767;
+
+// the workbook and what is needed to write its cells , moved to the blocking pool for every chunk
+struct XlsxSheet {
+    workbook: Workbook,
+    column_types: Vec<Type>,
+    date_format: Format,
+    datetime_format: Format,
+    time_format: Format,
+}
+
+impl XlsxSheet {
+    fn write_rows(&mut self, first_row: u32, rows: &[Vec<Value>]) -> Result<(), CustomError> {
+        let worksheet = self.workbook.worksheet_from_index(0).map_err(get_write_error)?;
+
+        for (offset, values) in rows.iter().enumerate() {
+            let r = first_row + offset as u32;
+            for (index, value) in values.iter().enumerate() {
+                let c = index as u16;
+                let result = match (value, &self.column_types[index]) {
+                    (Value::Null, _) => continue,
+                    (Value::Bool(b), _) => worksheet.write_boolean(r, c, *b),
+                    (Value::Number(n), _) => worksheet.write_number(r, c, n.as_f64().unwrap_or_default()),
+                    (Value::String(s), &Type::NUMERIC) => match s.parse::<f64>() {
+                        Ok(n) => worksheet.write_number(r, c, n),
+                        Err(_) => worksheet.write_string(r, c, s),
+                    },
+                    (Value::String(s), &Type::DATE) => match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+                        Ok(d) => worksheet.write_date_with_format(r, c, d, &self.date_format),
+                        Err(_) => worksheet.write_string(r, c, s),
+                    },
+                    (Value::String(s), &Type::TIMESTAMP) => match NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f") {
+                        Ok(d) => worksheet.write_datetime_with_format(r, c, d, &self.datetime_format),
+                        Err(_) => worksheet.write_string(r, c, s),
+                    },
+                    // timestamptz is written in UTC (excel dates have no time zone)
+                    (Value::String(s), &Type::TIMESTAMPTZ) => match DateTime::parse_from_rfc3339(s) {
+                        Ok(d) => worksheet.write_datetime_with_format(r, c, d.naive_utc(), &self.datetime_format),
+                        Err(_) => worksheet.write_string(r, c, s),
+                    },
+                    (Value::String(s), &Type::TIME) => match NaiveTime::parse_from_str(s, "%H:%M:%S%.f") {
+                        Ok(t) => worksheet.write_datetime_with_format(r, c, t, &self.time_format),
+                        Err(_) => worksheet.write_string(r, c, s),
+                    },
+                    (Value::String(s), _) => worksheet.write_string(r, c, truncate_string(s, XLSX_MAX_STRING_LENGTH)),
+                    // json / arrays
+                    (other, _) => worksheet.write_string(r, c, truncate_string(other.to_string().as_str(), XLSX_MAX_STRING_LENGTH)),
+                };
+                result.map_err(get_write_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// excel workbook with typed cells : numbers , booleans and dates are not written as text
+// the worksheet runs in 'constant memory' mode (rows are kept in a temp file , not in memory)
+pub struct XlsxWriter {
+    file_path: String,
+    sheet: Option<XlsxSheet>,
+    rows: Vec<Vec<Value>>,
+    row_number: u32,
+}
+
+impl XlsxWriter {
+    fn new(file_path: &str) -> XlsxWriter {
+        let mut workbook = Workbook::new();
+        workbook.add_worksheet_with_constant_memory();
+        XlsxWriter {
+            file_path: file_path.to_string(),
+            sheet: Some(XlsxSheet {
+                workbook,
+                column_types: vec![],
+                date_format: Format::new().set_num_format("yyyy-mm-dd"),
+                datetime_format: Format::new().set_num_format("yyyy-mm-dd hh:mm:ss"),
+                time_format: Format::new().set_num_format("hh:mm:ss"),
+            }),
+            rows: vec![],
+            row_number: 0,
+        }
+    }
+
+    // the sheet is only missing once the workbook has been saved
+    fn take_sheet(&mut self) -> Result<XlsxSheet, CustomError> {
+        self.sheet.take().ok_or_else(|| get_write_error("the workbook was already saved"))
+    }
+
+    // the cells of a chunk are written (and flushed to the temp file) on the blocking pool , the sheet is handed back
+    async fn write_buffered_rows(&mut self) -> Result<(), CustomError> {
+        if self.rows.is_empty() {
+            return Ok(())
+        }
+        let mut sheet = self.take_sheet()?;
+        let rows = std::mem::take(&mut self.rows);
+        let first_row = self.row_number - rows.len() as u32;
+
+        let sheet = run_blocking(move || {
+            sheet.write_rows(first_row, &rows)?;
+            Ok(sheet)
+        }).await?;
+        self.sheet = Some(sheet);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExportWriter for XlsxWriter {
+    async fn write_header(&mut self, columns: &[Column]) -> Result<(), CustomError> {
+        let sheet = self.sheet.as_mut().ok_or_else(|| get_write_error("the workbook was already saved"))?;
+        sheet.column_types = columns.iter().map(|col| col.type_().clone()).collect();
+        let bold = Format::new().set_bold();
+        let worksheet = sheet.workbook.worksheet_from_index(0).map_err(get_write_error)?;
+        for (index, column) in columns.iter().enumerate() {
+            worksheet.write_string_with_format(0, index as u16, column.name(), &bold).map_err(get_write_error)?;
+        }
+        self.row_number = 1;
+        Ok(())
+    }
+
+    async fn write_row(&mut self, row: &Row) -> Result<(), CustomError> {
+        if self.row_number >= XLSX_MAX_ROWS {
+            return Err(CustomError::ExportError(format!("an XLSX export is limited to {} rows, please narrow the search", XLSX_MAX_ROWS - 1)))
+        }
+
+        self.rows.push(row_to_json_values(row));
+        self.row_number += 1;
+        if self.rows.len() == EXPORT_CHUNK_ROWS {
+            self.write_buffered_rows().await?;
+        }
+        Ok(())
+    }
+
+    // the workbook is assembled (and its temp files read back) when it is saved
+    async fn finish(&mut self) -> Result<(), CustomError> {
+        self.write_buffered_rows().await?;
+        let mut sheet = self.take_sheet()?;
+        let file_path = self.file_path.to_string();
+        run_blocking(move || sheet.workbook.save(file_path.as_str()).map_err(get_write_error)).await
+    }
+}
+
+fn truncate_string(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((index, _)) => &s[..index],
+        None => s,
+    }
+}
+
+/* ************************************************************************************* */
+
+// rows per parquet row group
+const PARQUET_ROW_GROUP_ROWS: usize = 65_536;
+
+// bool / int / float columns keep their type , every other column is written as text
+// (same text as the CSV export)
+pub struct ParquetWriter {
+    file: File,
+    schema: SchemaRef,
+    writer: Option<ArrowWriter<Vec<u8>>>,
+    builders: Vec<Box<dyn ArrayBuilder>>,
+    chunk_rows: usize,
+}
+
+impl ParquetWriter {
+    fn new(file: File) -> ParquetWriter {
+        ParquetWriter {
+            file,
+            schema: Arc::new(Schema::empty()),
+            writer: None,
+            builders: vec![],
+            chunk_rows: 0,
+        }
+    }
+
+    // every chunk becomes a record batch , the bytes of the row groups the
+    // arrow writer has completed so far are moved to the file
+    async fn write_buffered_rows(&mut self) -> Result<(), CustomError> {
+        let mut writer = match self.writer.take() {
+            Some(w) => w,
+            None => return Ok(())
+        };
+
+        let batch = if self.chunk_rows > 0 {
+            let arrays: Vec<ArrayRef> = self.builders.iter_mut().map(|b| b.finish()).collect();
+            self.chunk_rows = 0;
+            Some(RecordBatch::try_new(self.schema.clone(), arrays).map_err(get_write_error)?)
+        } else {
+            None
+        };
+
+        // the writer encodes (and compresses a full row group) on the blocking pool and is handed back
+        let (writer, chunk) = run_blocking(move || {
+            if let Some(batch) = batch {
+                writer.write(&batch).map_err(get_write_error)?;
+            }
+            let chunk = std::mem::take(writer.inner_mut());
+            Ok((writer, chunk))
+        }).await?;
+        self.writer = Some(writer);
+
+        write_chunk(&mut self.file, &chunk).await
+    }
+}
+
+fn get_parquet_data_type(column_type: &Type) -> DataType {
+    match *column_type {
+        Type::BOOL => DataType::Boolean,
+        Type::INT2 | Type::INT4 => DataType::Int32,
+        Type::INT8 | Type::OID => DataType::Int64,
+        Type::FLOAT4 | Type::FLOAT8 => DataType::Float64,
+        _ => DataType::Utf8,
+    }
+}
+
+fn make_parquet_builder(data_type: &DataType) -> Box<dyn ArrayBuilder> {
+    match data_type {
+        DataType::Boolean => Box::new(BooleanBuilder::new()),
+        DataType::Int32 => Box::new(Int32Builder::new()),
+        DataType::Int64 => Box::new(Int64Builder::new()),
+        DataType::Float64 => Box::new(Float64Builder::new()),
+        _ => Box::new(StringBuilder::new()),
+    }
+}
+
+fn append_parquet_value(builder: &mut Box<dyn ArrayBuilder>, value: &Value) {
+    let builder = builder.as_any_mut();
+    if let Some(b) = builder.downcast_mut::<BooleanBuilder>() {
+        b.append_option(value.as_bool());
+    } else if let Some(b) = builder.downcast_mut::<Int32Builder>() {
+        b.append_option(value.as_i64().map(|v| v as i32));
+    } else if let Some(b) = builder.downcast_mut::<Int64Builder>() {
+        b.append_option(value.as_i64());
+    } else if let Some(b) = builder.downcast_mut::<Float64Builder>() {
+        // NaN / Infinity arrive as strings (see float_to_json)
+        b.append_option(value.as_f64().or_else(|| value.as_str().and_then(|s| s.parse::<f64>().ok())));
+    } else if let Some(b) = builder.downcast_mut::<StringBuilder>() {
+        match value {
+            Value::Null => b.append_null(),
+            other => b.append_value(json_value_to_csv_field(other)),
+        }
+    }
+}
+
+#[async_trait]
+impl ExportWriter for ParquetWriter {
+    async fn write_header(&mut self, columns: &[Column]) -> Result<(), CustomError> {
+        let fields: Vec<Field> = columns.iter().map(|col| Field::new(col.name(), get_parquet_data_type(col.type_()), true)).collect();
+        self.schema = Arc::new(Schema::new(fields));
+        self.builders = self.schema.fields().iter().map(|f| make_parquet_builder(f.data_type())).collect();
+
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(PARQUET_ROW_GROUP_ROWS)
+            .build();
+        self.writer = Some(ArrowWriter::try_new(vec![], self.schema.clone(), Some(properties)).map_err(get_write_error)?);
+        Ok(())
+    }
+
+    async fn write_row(&mut self, row: &Row) -> Result<(), CustomError> {
+        for (builder, value) in self.builders.iter_mut().zip(row_to_json_values(row).iter()) {
+            append_parquet_value(builder, value);
+        }
+        self.chunk_rows += 1;
+        if self.chunk_rows == EXPORT_CHUNK_ROWS {
+            self.write_buffered_rows().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), CustomError> {
+        self.write_buffered_rows().await?;
+        if let Some(mut writer) = self.writer.take() {
+            let chunk = run_blocking(move || {
+                writer.finish().map_err(get_write_error)?;
+                Ok(std::mem::take(writer.inner_mut()))
+            }).await?;
+            write_chunk(&mut self.file, &chunk).await?;
+        }
+        flush_file(&mut self.file).await
+    }
+}